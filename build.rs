@@ -0,0 +1,240 @@
+// Generates the attack and magic bitboard tables used by move generation.
+//
+// This is a port of util/get_bitboard.py.  The tables are written to
+// $OUT_DIR/magic.rs and pulled into the crate by src/magic.rs, so a clean
+// checkout builds with nothing but cargo.
+
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::path::Path;
+
+const ROOK_HASH_BITS: u32 = 12;
+const BISHOP_HASH_BITS: u32 = 9;
+
+// Fixed seed so every build produces identical tables
+const MAGIC_SEED: u64 = 0x52616e61726f6f6b;
+
+const KNIGHT_DELTAS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_DELTAS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest = Path::new(&out_dir).join("magic.rs");
+
+    let mut rng_state = MAGIC_SEED;
+    let mut out = String::new();
+
+    write_table(&mut out, "knight_collisions", &step_table(&KNIGHT_DELTAS));
+    write_table(&mut out, "king_collisions", &step_table(&KING_DELTAS));
+    write_table(&mut out, "w_pawn_move_collisions", &step_table(&[(0, 1)]));
+    write_table(&mut out, "b_pawn_move_collisions", &step_table(&[(0, -1)]));
+    write_table(
+        &mut out,
+        "w_pawn_attack_collisions",
+        &step_table(&[(1, 1), (-1, 1)]),
+    );
+    write_table(
+        &mut out,
+        "b_pawn_attack_collisions",
+        &step_table(&[(1, -1), (-1, -1)]),
+    );
+
+    let rook_masks = mask_table(&ROOK_DIRECTIONS);
+    let bishop_masks = mask_table(&BISHOP_DIRECTIONS);
+    write_table(&mut out, "rook_collisions", &rook_masks);
+    write_table(&mut out, "bishop_collisions", &bishop_masks);
+
+    let (rook_magics, rook_sets) = magic_tables(
+        &rook_masks,
+        &ROOK_DIRECTIONS,
+        ROOK_HASH_BITS,
+        &mut rng_state,
+    );
+    let (bishop_magics, bishop_sets) = magic_tables(
+        &bishop_masks,
+        &BISHOP_DIRECTIONS,
+        BISHOP_HASH_BITS,
+        &mut rng_state,
+    );
+    write_table(&mut out, "rook_magic_numbers", &rook_magics);
+    write_table(&mut out, "bishop_magic_numbers", &bishop_magics);
+    write_move_sets(&mut out, "rook_magic_move_sets", &rook_sets, ROOK_HASH_BITS);
+    write_move_sets(
+        &mut out,
+        "bishop_magic_move_sets",
+        &bishop_sets,
+        BISHOP_HASH_BITS,
+    );
+
+    fs::write(&dest, out).unwrap();
+}
+
+fn on_board(col: i32, row: i32) -> bool {
+    (0..8).contains(&col) && (0..8).contains(&row)
+}
+
+// Squares reachable by a single step in any of the given (col, row) deltas
+fn step_table(deltas: &[(i32, i32)]) -> Vec<u64> {
+    let mut table = Vec::new();
+    for i in 0..64 {
+        let (col, row) = (i % 8, i / 8);
+        let mut bb = 0;
+        for (dc, dr) in deltas.iter() {
+            if on_board(col + dc, row + dr) {
+                bb |= 0x1 << ((row + dr) * 8 + col + dc);
+            }
+        }
+        table.push(bb);
+    }
+    table
+}
+
+// Relevant occupancy squares for a slider.  The final square of every ray is
+// dropped since a blocker there cannot change the move set.
+fn mask_table(directions: &[(i32, i32)]) -> Vec<u64> {
+    let mut table = Vec::new();
+    for i in 0..64 {
+        let (col, row) = (i % 8, i / 8);
+        let mut bb = 0;
+        for (dc, dr) in directions.iter() {
+            let (mut c, mut r) = (col + dc, row + dr);
+            while on_board(c + dc, r + dr) {
+                bb |= 0x1 << (r * 8 + c);
+                c += dc;
+                r += dr;
+            }
+        }
+        table.push(bb);
+    }
+    table
+}
+
+// Walks each ray until it leaves the board or lands on an occupied square
+fn occupation_moves(i: i32, directions: &[(i32, i32)], occupied: u64) -> u64 {
+    let (col, row) = (i % 8, i / 8);
+    let mut bb = 0;
+    for (dc, dr) in directions.iter() {
+        let (mut c, mut r) = (col + dc, row + dr);
+        while on_board(c, r) {
+            let sq: u64 = 0x1 << (r * 8 + c);
+            bb |= sq;
+            if occupied & sq > 0 {
+                break;
+            }
+            c += dc;
+            r += dr;
+        }
+    }
+    bb
+}
+
+fn magic_tables(
+    masks: &[u64],
+    directions: &[(i32, i32)],
+    hash_bits: u32,
+    rng_state: &mut u64,
+) -> (Vec<u64>, Vec<Vec<u64>>) {
+    let mut magic_nums = Vec::new();
+    let mut placed_moves = Vec::new();
+
+    for i in 0..64 {
+        let mask = masks[i as usize];
+
+        // Enumerate every subset of the mask along with its move set
+        let mut occupied_move_map = Vec::new();
+        let mut subset: u64 = 0;
+        loop {
+            occupied_move_map.push((subset, occupation_moves(i, directions, subset)));
+            subset = subset.wrapping_sub(mask) & mask;
+            if subset == 0 {
+                break;
+            }
+        }
+
+        let mut new_array = vec![0; 1 << hash_bits];
+        loop {
+            // Sparse candidates hash far better than uniform ones
+            let new_rand = next_rand(rng_state) & next_rand(rng_state) & next_rand(rng_state);
+            if (mask.wrapping_mul(new_rand) >> 56).count_ones() < 6 {
+                continue;
+            }
+
+            for entry in new_array.iter_mut() {
+                *entry = 0;
+            }
+            let mut perfect_hash = true;
+            for (occupied, moves) in occupied_move_map.iter() {
+                let index = (occupied.wrapping_mul(new_rand) >> (64 - hash_bits)) as usize;
+                if new_array[index] == 0 || new_array[index] == *moves {
+                    new_array[index] = *moves;
+                } else {
+                    perfect_hash = false;
+                    break;
+                }
+            }
+
+            if perfect_hash {
+                magic_nums.push(new_rand);
+                placed_moves.push(new_array);
+                break;
+            }
+        }
+    }
+
+    (magic_nums, placed_moves)
+}
+
+// xorshift64*
+fn next_rand(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545f4914f6cdd1d)
+}
+
+fn write_table(out: &mut String, name: &str, table: &[u64]) {
+    writeln!(out, "pub static {}: [u64; {}] = [", name, table.len()).unwrap();
+    for value in table.iter() {
+        writeln!(out, "    {:#x},", value).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+fn write_move_sets(out: &mut String, name: &str, sets: &[Vec<u64>], hash_bits: u32) {
+    writeln!(
+        out,
+        "pub static {}: [[u64; {}]; {}] = [",
+        name,
+        1 << hash_bits,
+        sets.len()
+    )
+    .unwrap();
+    for set in sets.iter() {
+        let values: Vec<String> = set.iter().map(|v| format!("{:#x}", v)).collect();
+        writeln!(out, "    [{}],", values.join(",")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
@@ -0,0 +1,88 @@
+use crate::board::Board;
+use crate::search;
+use crate::tree::Tree;
+use crate::{UciGo, UciOption};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const BENCH_THREADS: [usize; 4] = [1, 2, 4, 8];
+
+const BENCH_FENS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+// Searches every bench position for the given time on each thread count and
+// returns the nodes per second reached with each
+pub fn bench(options: &[UciOption], millis: u64) -> Vec<(usize, u64)> {
+    BENCH_THREADS
+        .iter()
+        .map(|&threads| {
+            let mut nodes = 0;
+            let mut elapsed = Duration::default();
+            for fen in BENCH_FENS.iter() {
+                let (n, e) = search_for(fen, options, threads, millis);
+                nodes += n;
+                elapsed += e;
+            }
+            let nps = nodes as u128 * 1000 / elapsed.as_millis().max(1);
+            (threads, nps as u64)
+        })
+        .collect()
+}
+
+// Runs an infinite search on a fresh tree and counts its nodes.  The first
+// thread takes the main role as it does under go, so a full tree is pruned
+// the same way, and prints its info and bestmove when the time is up.
+fn search_for(fen: &str, options: &[UciOption], threads: usize, millis: u64) -> (u64, Duration) {
+    let tree = Arc::new(Tree::new(Board::new(fen), Vec::new()));
+    let searching = Arc::new(Mutex::new(true));
+    let go_cmd = UciGo {
+        infinite: true,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|id| {
+            let tree = Arc::clone(&tree);
+            let options = options.to_vec();
+            let searching = Arc::clone(&searching);
+            let go_cmd = go_cmd.clone();
+            thread::spawn(move || {
+                search::search(
+                    tree,
+                    options,
+                    searching,
+                    Arc::new(Mutex::new(false)),
+                    go_cmd,
+                    id == 0,
+                )
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(millis));
+    *searching.lock().unwrap() = false;
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    (u64::from(tree.node(tree.root()).visits()), start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_counts_nodes() {
+        let (options, _) = crate::initialize();
+        let (nodes, elapsed) = search_for(BENCH_FENS[0], &options, 2, 50);
+        assert!(nodes > 100);
+        assert!(elapsed >= Duration::from_millis(50));
+    }
+}
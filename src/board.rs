@@ -1,5 +1,9 @@
+use crate::move_gen;
+use crate::zobrist;
 use std::fmt;
 
+const BACK_RANKS_BB: u64 = 0xff000000000000ff;
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Board {
     pub w_p_bb: u64,
@@ -22,9 +26,10 @@ pub struct Board {
     pub en_passent: Option<u64>,
     pub halfmove_clock: u32,
     pub fullmove_clock: u32,
+    pub hash: u64,
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PieceType {
     WP,
     WN,
@@ -60,15 +65,73 @@ impl fmt::Display for PieceType {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    FieldCount(usize),
+    Piece(char),
+    RankLength(u32),
+    RankCount,
+    SideToMove(String),
+    Castling(char),
+    EnPassent(String),
+    HalfmoveClock(String),
+    FullmoveClock(String),
+    KingCount,
+    PawnOnBackRank,
+    OpponentInCheck,
+    CastlingRights(char),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::FieldCount(n) => write!(f, "expected 6 fields, found {}", n),
+            FenError::Piece(c) => write!(f, "invalid character in board: {}", c),
+            FenError::RankLength(r) => write!(f, "rank {} does not have 8 squares", r),
+            FenError::RankCount => write!(f, "board does not have 8 ranks"),
+            FenError::SideToMove(s) => write!(f, "invalid side to move: {}", s),
+            FenError::Castling(c) => write!(f, "invalid character in castling rights: {}", c),
+            FenError::EnPassent(s) => write!(f, "invalid en passent square: {}", s),
+            FenError::HalfmoveClock(s) => write!(f, "invalid halfmove clock: {}", s),
+            FenError::FullmoveClock(s) => write!(f, "invalid fullmove clock: {}", s),
+            FenError::KingCount => write!(f, "each side needs exactly one king"),
+            FenError::PawnOnBackRank => write!(f, "pawns cannot stand on the first or last rank"),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            FenError::CastlingRights(c) => {
+                write!(f, "castling right {} needs the king and rook at home", c)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    Malformed(String),
+    Illegal(String),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::Malformed(m) => write!(f, "malformed move: {}", m),
+            MoveError::Illegal(m) => write!(f, "illegal move: {}", m),
+        }
+    }
+}
+
 impl Board {
     pub fn new(fen: &str) -> Board {
-        let fen_tokens: Vec<String> = fen
-            .split_ascii_whitespace()
-            .map(|x| String::from(x))
-            .collect();
+        match Board::from_fen(fen) {
+            Ok(board) => board,
+            Err(e) => panic!("Invalid fen {}: {}", fen, e),
+        }
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let fen_tokens: Vec<String> = fen.split_ascii_whitespace().map(String::from).collect();
 
         if fen_tokens.len() != 6 {
-            panic!("Invalid fen: {}", fen);
+            return Err(FenError::FieldCount(fen_tokens.len()));
         }
 
         let mut w_p_bb = 0;
@@ -88,63 +151,96 @@ impl Board {
         let mut col: u32 = 0;
         for c in fen_tokens[0].chars() {
             match c {
-                'p' => b_p_bb |= 0x1 << (row * 8 + col),
-                'n' => b_n_bb |= 0x1 << (row * 8 + col),
-                'b' => b_b_bb |= 0x1 << (row * 8 + col),
-                'r' => b_r_bb |= 0x1 << (row * 8 + col),
-                'q' => b_q_bb |= 0x1 << (row * 8 + col),
-                'k' => b_k_bb |= 0x1 << (row * 8 + col),
-                'P' => w_p_bb |= 0x1 << (row * 8 + col),
-                'N' => w_n_bb |= 0x1 << (row * 8 + col),
-                'B' => w_b_bb |= 0x1 << (row * 8 + col),
-                'R' => w_r_bb |= 0x1 << (row * 8 + col),
-                'Q' => w_q_bb |= 0x1 << (row * 8 + col),
-                'K' => w_k_bb |= 0x1 << (row * 8 + col),
-                n @ '1'..='8' => col += n.to_digit(10).unwrap() - 1,
+                n @ '1'..='8' => {
+                    col += n.to_digit(10).unwrap();
+                    if col > 8 {
+                        return Err(FenError::RankLength(8 - row));
+                    }
+                    continue;
+                }
                 '/' => {
+                    if col != 8 {
+                        return Err(FenError::RankLength(8 - row));
+                    }
+                    if row == 0 {
+                        return Err(FenError::RankCount);
+                    }
                     row -= 1;
                     col = 0;
                     continue;
                 }
-                _ => panic!("Invalid character in fen board: {}", c),
+                _ => (),
+            }
+            if col > 7 {
+                return Err(FenError::RankLength(8 - row));
+            }
+            let pos: u64 = 0x1 << (row * 8 + col);
+            match c {
+                'p' => b_p_bb |= pos,
+                'n' => b_n_bb |= pos,
+                'b' => b_b_bb |= pos,
+                'r' => b_r_bb |= pos,
+                'q' => b_q_bb |= pos,
+                'k' => b_k_bb |= pos,
+                'P' => w_p_bb |= pos,
+                'N' => w_n_bb |= pos,
+                'B' => w_b_bb |= pos,
+                'R' => w_r_bb |= pos,
+                'Q' => w_q_bb |= pos,
+                'K' => w_k_bb |= pos,
+                _ => return Err(FenError::Piece(c)),
             }
             col += 1;
         }
+        if row != 0 {
+            return Err(FenError::RankCount);
+        }
+        if col != 8 {
+            return Err(FenError::RankLength(8));
+        }
 
-        let is_white_move = if fen_tokens[1] == "w" { true } else { false };
+        let is_white_move = match fen_tokens[1].as_str() {
+            "w" => true,
+            "b" => false,
+            _ => return Err(FenError::SideToMove(fen_tokens[1].clone())),
+        };
 
         let mut w_castle = false;
         let mut w_q_castle = false;
         let mut b_castle = false;
         let mut b_q_castle = false;
 
-        for c in fen_tokens[2].chars() {
-            match c {
-                'K' => w_castle = true,
-                'Q' => w_q_castle = true,
-                'k' => b_castle = true,
-                'q' => b_q_castle = true,
-                '-' => (),
-                _ => panic!("Invalid character in fen castling rights: {}", c),
+        if fen_tokens[2] != "-" {
+            for c in fen_tokens[2].chars() {
+                match c {
+                    'K' => w_castle = true,
+                    'Q' => w_q_castle = true,
+                    'k' => b_castle = true,
+                    'q' => b_q_castle = true,
+                    _ => return Err(FenError::Castling(c)),
+                }
             }
         }
 
         let en_p_str = &fen_tokens[3];
-        let en_passent = if en_p_str.chars().count() == 2 {
+        let en_passent = if en_p_str != "-" {
             let mut iter = en_p_str.chars();
-            let col = iter.next().unwrap();
-            let mut row: u32 = iter.next().unwrap().to_digit(10).unwrap();
-            row -= 1;
-            let col = match col {
-                'a' => 0,
-                'b' => 1,
-                'c' => 2,
-                'd' => 3,
-                'e' => 4,
-                'f' => 5,
-                'g' => 6,
-                'h' => 7,
-                _ => panic!("Invalid character in fen en passent: {}", col),
+            let col = match iter.next() {
+                Some('a') => 0,
+                Some('b') => 1,
+                Some('c') => 2,
+                Some('d') => 3,
+                Some('e') => 4,
+                Some('f') => 5,
+                Some('g') => 6,
+                Some('h') => 7,
+                _ => return Err(FenError::EnPassent(en_p_str.clone())),
+            };
+            // Only the square behind a pawn that just moved two squares is valid
+            let row = match (iter.next(), iter.next(), is_white_move) {
+                (Some('6'), None, true) => 5,
+                (Some('3'), None, false) => 2,
+                _ => return Err(FenError::EnPassent(en_p_str.clone())),
             };
 
             Some(0x1 << (row * 8 + col))
@@ -152,31 +248,117 @@ impl Board {
             None
         };
 
-        let halfmove = fen_tokens[4].parse().unwrap();
-        let fullmove = fen_tokens[5].parse().unwrap();
-
-        Board {
-            w_p_bb: w_p_bb,
-            w_n_bb: w_n_bb,
-            w_b_bb: w_b_bb,
-            w_r_bb: w_r_bb,
-            w_q_bb: w_q_bb,
-            w_k_bb: w_k_bb,
-            b_p_bb: b_p_bb,
-            b_n_bb: b_n_bb,
-            b_b_bb: b_b_bb,
-            b_r_bb: b_r_bb,
-            b_q_bb: b_q_bb,
-            b_k_bb: b_k_bb,
+        let halfmove = match fen_tokens[4].parse() {
+            Ok(h) => h,
+            Err(_) => return Err(FenError::HalfmoveClock(fen_tokens[4].clone())),
+        };
+        let fullmove = match fen_tokens[5].parse() {
+            Ok(f) => f,
+            Err(_) => return Err(FenError::FullmoveClock(fen_tokens[5].clone())),
+        };
+
+        let mut board = Board {
+            w_p_bb,
+            w_n_bb,
+            w_b_bb,
+            w_r_bb,
+            w_q_bb,
+            w_k_bb,
+            b_p_bb,
+            b_n_bb,
+            b_b_bb,
+            b_r_bb,
+            b_q_bb,
+            b_k_bb,
             is_w_move: is_white_move,
             is_w_castle: w_castle,
             is_w_q_castle: w_q_castle,
             is_b_castle: b_castle,
             is_b_q_castle: b_q_castle,
-            en_passent: en_passent,
+            en_passent,
             halfmove_clock: halfmove,
             fullmove_clock: fullmove,
+            hash: 0,
+        };
+        board.hash = zobrist::hash(&board);
+        board.validate()?;
+        Ok(board)
+    }
+
+    // Checks that a parsed position could actually occur in a game
+    fn validate(&self) -> Result<(), FenError> {
+        if self.w_k_bb.count_ones() != 1 || self.b_k_bb.count_ones() != 1 {
+            return Err(FenError::KingCount);
+        }
+        if (self.w_p_bb | self.b_p_bb) & BACK_RANKS_BB > 0 {
+            return Err(FenError::PawnOnBackRank);
         }
+
+        // The side that just moved cannot have left its king in check
+        let (king_bb, by_white) = if self.is_w_move {
+            (self.b_k_bb, true)
+        } else {
+            (self.w_k_bb, false)
+        };
+        if move_gen::is_attacked(self, by_white, king_bb) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        // Castling rights need the king and rook on their starting squares
+        if (self.is_w_castle || self.is_w_q_castle) && self.w_k_bb != 0x10 {
+            return Err(FenError::CastlingRights(if self.is_w_castle {
+                'K'
+            } else {
+                'Q'
+            }));
+        }
+        if self.is_w_castle && self.w_r_bb & 0x80 == 0 {
+            return Err(FenError::CastlingRights('K'));
+        }
+        if self.is_w_q_castle && self.w_r_bb & 0x1 == 0 {
+            return Err(FenError::CastlingRights('Q'));
+        }
+        if (self.is_b_castle || self.is_b_q_castle) && self.b_k_bb != 0x1000000000000000 {
+            return Err(FenError::CastlingRights(if self.is_b_castle {
+                'k'
+            } else {
+                'q'
+            }));
+        }
+        if self.is_b_castle && self.b_r_bb & 0x8000000000000000 == 0 {
+            return Err(FenError::CastlingRights('k'));
+        }
+        if self.is_b_q_castle && self.b_r_bb & 0x100000000000000 == 0 {
+            return Err(FenError::CastlingRights('q'));
+        }
+
+        Ok(())
+    }
+
+    // Applies the move only if it is legal in this position
+    pub fn try_move(&mut self, mov: &str) -> Result<(), MoveError> {
+        let chars: Vec<char> = mov.chars().collect();
+        let well_formed = (chars.len() == 4 || chars.len() == 5)
+            && ('a'..='h').contains(&chars[0])
+            && ('1'..='8').contains(&chars[1])
+            && ('a'..='h').contains(&chars[2])
+            && ('1'..='8').contains(&chars[3])
+            && (chars.len() == 4 || "nbrqNBRQ".contains(chars[4]));
+        if !well_formed {
+            return Err(MoveError::Malformed(mov.to_string()));
+        }
+
+        // Promotions may arrive in either case, generated moves are lowercase
+        let mov_lower = mov.to_ascii_lowercase();
+        if !move_gen::legal_moves(self)
+            .iter()
+            .any(|m| m.to_string() == mov_lower)
+        {
+            return Err(MoveError::Illegal(mov.to_string()));
+        }
+
+        self.do_move(&mov_lower);
+        Ok(())
     }
 
     pub fn do_move(&mut self, mov: &str) {
@@ -270,6 +452,9 @@ impl Board {
             None
         };
 
+        // Remove side to move, castling and en passent from the hash
+        self.hash ^= zobrist::state(self);
+
         //Increment move counters
         self.halfmove_clock += 1;
         if !self.is_w_move {
@@ -295,6 +480,8 @@ impl Board {
                 self.is_w_q_castle = false;
                 self.w_r_bb &= !0x80;
                 self.w_r_bb |= 0x20;
+                self.hash ^=
+                    zobrist::piece(PieceType::WR, 0x80) ^ zobrist::piece(PieceType::WR, 0x20);
             }
             //Queenside
             else if from_ind == 4 && to_ind == 2 {
@@ -302,6 +489,8 @@ impl Board {
                 self.is_w_q_castle = false;
                 self.w_r_bb &= !0x1;
                 self.w_r_bb |= 0x8;
+                self.hash ^=
+                    zobrist::piece(PieceType::WR, 0x1) ^ zobrist::piece(PieceType::WR, 0x8);
             }
         }
         // Check for black castling
@@ -312,6 +501,8 @@ impl Board {
                 self.is_b_q_castle = false;
                 self.b_r_bb &= !0x8000000000000000;
                 self.b_r_bb |= 0x2000000000000000;
+                self.hash ^= zobrist::piece(PieceType::BR, 0x8000000000000000)
+                    ^ zobrist::piece(PieceType::BR, 0x2000000000000000);
             }
             //Queenside
             if from_ind == 60 && to_ind == 58 {
@@ -319,12 +510,15 @@ impl Board {
                 self.is_b_q_castle = false;
                 self.b_r_bb &= !0x100000000000000;
                 self.b_r_bb |= 0x800000000000000;
+                self.hash ^= zobrist::piece(PieceType::BR, 0x100000000000000)
+                    ^ zobrist::piece(PieceType::BR, 0x800000000000000);
             }
         }
 
         // Delete the landing square
-        match to_pt {
-            Some(ref pt) => match pt {
+        if let Some(ref pt) = to_pt {
+            self.hash ^= zobrist::piece(*pt, to_pos);
+            match pt {
                 PieceType::WP => {
                     self.w_p_bb &= !(to_pos);
                     self.halfmove_clock = 0;
@@ -383,20 +577,21 @@ impl Board {
                     self.b_k_bb &= !(to_pos);
                     self.halfmove_clock = 0;
                 }
-            },
-            None => (),
+            }
         }
 
         // Check for en-passent capture
-        if to_pt == None
+        if to_pt.is_none()
             && (from_pt == PieceType::WP || from_pt == PieceType::BP)
             && to_col != from_col
         {
             // Remove captured pawn
             if from_pt == PieceType::WP {
                 self.b_p_bb &= !(0x1 << (to_ind - 8));
+                self.hash ^= zobrist::piece(PieceType::BP, 0x1 << (to_ind - 8));
             } else {
                 self.w_p_bb &= !(0x1 << (to_ind + 8));
+                self.hash ^= zobrist::piece(PieceType::WP, 0x1 << (to_ind + 8));
             }
             self.halfmove_clock = 0;
         }
@@ -406,61 +601,76 @@ impl Board {
             Some(c) => {
                 // A promotion!
                 // Add new piece
-                if self.is_w_move {
+                let promoted = if self.is_w_move {
                     match c {
-                        'n' | 'N' => self.w_n_bb |= to_pos,
-                        'b' | 'B' => self.w_b_bb |= to_pos,
-                        'r' | 'R' => self.w_r_bb |= to_pos,
-                        'q' | 'Q' => self.w_q_bb |= to_pos,
+                        'n' | 'N' => PieceType::WN,
+                        'b' | 'B' => PieceType::WB,
+                        'r' | 'R' => PieceType::WR,
+                        'q' | 'Q' => PieceType::WQ,
                         _ => panic!("Invalid promotion piece: {}", c),
                     }
                 } else {
                     match c {
-                        'n' | 'N' => self.b_n_bb |= to_pos,
-                        'b' | 'B' => self.b_b_bb |= to_pos,
-                        'r' | 'R' => self.b_r_bb |= to_pos,
-                        'q' | 'Q' => self.b_q_bb |= to_pos,
+                        'n' | 'N' => PieceType::BN,
+                        'b' | 'B' => PieceType::BB,
+                        'r' | 'R' => PieceType::BR,
+                        'q' | 'Q' => PieceType::BQ,
                         _ => panic!("Invalid promotion piece: {}", c),
                     }
+                };
+                match promoted {
+                    PieceType::WN => self.w_n_bb |= to_pos,
+                    PieceType::WB => self.w_b_bb |= to_pos,
+                    PieceType::WR => self.w_r_bb |= to_pos,
+                    PieceType::WQ => self.w_q_bb |= to_pos,
+                    PieceType::BN => self.b_n_bb |= to_pos,
+                    PieceType::BB => self.b_b_bb |= to_pos,
+                    PieceType::BR => self.b_r_bb |= to_pos,
+                    PieceType::BQ => self.b_q_bb |= to_pos,
+                    _ => panic!("Invalid promotion piece: {}", promoted),
                 }
+                self.hash ^= zobrist::piece(promoted, to_pos);
             }
             //Regular move
-            None => match from_pt {
-                PieceType::WP => self.w_p_bb |= to_pos,
-                PieceType::WN => self.w_n_bb |= to_pos,
-                PieceType::WB => self.w_b_bb |= to_pos,
-                PieceType::WR => {
-                    self.w_r_bb |= to_pos;
-                    if from_pos == 0x1 {
-                        self.is_w_q_castle = false;
-                    } else if from_pos == 0x80 {
+            None => {
+                self.hash ^= zobrist::piece(from_pt, to_pos);
+                match from_pt {
+                    PieceType::WP => self.w_p_bb |= to_pos,
+                    PieceType::WN => self.w_n_bb |= to_pos,
+                    PieceType::WB => self.w_b_bb |= to_pos,
+                    PieceType::WR => {
+                        self.w_r_bb |= to_pos;
+                        if from_pos == 0x1 {
+                            self.is_w_q_castle = false;
+                        } else if from_pos == 0x80 {
+                            self.is_w_castle = false;
+                        }
+                    }
+                    PieceType::WQ => self.w_q_bb |= to_pos,
+                    PieceType::WK => {
+                        self.w_k_bb |= to_pos;
                         self.is_w_castle = false;
+                        self.is_w_q_castle = false;
                     }
-                }
-                PieceType::WQ => self.w_q_bb |= to_pos,
-                PieceType::WK => {
-                    self.w_k_bb |= to_pos;
-                    self.is_w_castle = false;
-                    self.is_w_q_castle = false;
-                }
-                PieceType::BP => self.b_p_bb |= to_pos,
-                PieceType::BN => self.b_n_bb |= to_pos,
-                PieceType::BB => self.b_b_bb |= to_pos,
-                PieceType::BR => {
-                    self.b_r_bb |= to_pos;
-                    if from_pos == 0x100000000000000 {
-                        self.is_b_q_castle = false;
-                    } else if from_pos == 0x8000000000000000 {
+                    PieceType::BP => self.b_p_bb |= to_pos,
+                    PieceType::BN => self.b_n_bb |= to_pos,
+                    PieceType::BB => self.b_b_bb |= to_pos,
+                    PieceType::BR => {
+                        self.b_r_bb |= to_pos;
+                        if from_pos == 0x100000000000000 {
+                            self.is_b_q_castle = false;
+                        } else if from_pos == 0x8000000000000000 {
+                            self.is_b_castle = false;
+                        }
+                    }
+                    PieceType::BQ => self.b_q_bb |= to_pos,
+                    PieceType::BK => {
+                        self.b_k_bb |= to_pos;
                         self.is_b_castle = false;
+                        self.is_b_q_castle = false;
                     }
                 }
-                PieceType::BQ => self.b_q_bb |= to_pos,
-                PieceType::BK => {
-                    self.b_k_bb |= to_pos;
-                    self.is_b_castle = false;
-                    self.is_b_q_castle = false;
-                }
-            },
+            }
         }
 
         // Check if pawn move to reset halfmove counter
@@ -469,6 +679,7 @@ impl Board {
         }
 
         // Clear moving piece
+        self.hash ^= zobrist::piece(from_pt, from_pos);
         match from_pt {
             PieceType::WP => self.w_p_bb &= !(0x1 << from_ind),
             PieceType::WN => self.w_n_bb &= !(0x1 << from_ind),
@@ -486,6 +697,10 @@ impl Board {
 
         // Set the side to move
         self.is_w_move = !self.is_w_move;
+
+        // Add the new side to move, castling and en passent to the hash
+        self.hash ^= zobrist::state(self);
+        debug_assert_eq!(self.hash, zobrist::hash(self));
     }
 }
 
@@ -569,21 +784,17 @@ impl fmt::Display for Board {
         }
 
         string.push(' ');
-        match self.is_w_castle {
-            true => string.push('K'),
-            false => (),
+        if self.is_w_castle {
+            string.push('K')
         }
-        match self.is_w_q_castle {
-            true => string.push('Q'),
-            false => (),
+        if self.is_w_q_castle {
+            string.push('Q')
         }
-        match self.is_b_castle {
-            true => string.push('k'),
-            false => (),
+        if self.is_b_castle {
+            string.push('k')
         }
-        match self.is_b_q_castle {
-            true => string.push('q'),
-            false => (),
+        if self.is_b_q_castle {
+            string.push('q')
         }
         if !(self.is_w_castle || self.is_w_q_castle || self.is_b_castle || self.is_b_q_castle) {
             string.push('-');
@@ -620,3 +831,125 @@ impl fmt::Display for Board {
         write!(f, "{}", string)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_fen_errors() {
+        let cases = [
+            ("8/8/8/8/8/8/8/8 w - - 0", FenError::FieldCount(5)),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+                FenError::Piece('X'),
+            ),
+            (
+                "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                FenError::Piece('9'),
+            ),
+            (
+                "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                FenError::RankLength(2),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                FenError::RankCount,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+                FenError::SideToMove("x".to_string()),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQxq - 0 1",
+                FenError::Castling('x'),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e4 0 1",
+                FenError::EnPassent("e4".to_string()),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq i3 0 1",
+                FenError::EnPassent("i3".to_string()),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+                FenError::HalfmoveClock("x".to_string()),
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 -1",
+                FenError::FullmoveClock("-1".to_string()),
+            ),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::KingCount),
+            ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::KingCount),
+            ("4k2P/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank),
+            ("4k3/8/8/8/8/8/8/4K2p w - - 0 1", FenError::PawnOnBackRank),
+            ("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", FenError::OpponentInCheck),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w Q - 0 1",
+                FenError::CastlingRights('Q'),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/3K3R w K - 0 1",
+                FenError::CastlingRights('K'),
+            ),
+            (
+                "r3k3/8/8/8/8/8/8/4K3 w k - 0 1",
+                FenError::CastlingRights('k'),
+            ),
+        ];
+
+        for (fen, error) in cases.iter() {
+            assert_eq!(Board::from_fen(fen), Err(error.clone()), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn from_fen_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppp1ppp/8/8/3pP3/8/PPP2PPP/RNBQKBNR b KQkq e3 0 3",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 12 40",
+        ];
+        for fen in fens.iter() {
+            assert_eq!(Board::from_fen(fen).unwrap().to_string(), *fen);
+        }
+    }
+
+    #[test]
+    fn try_move() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let cases = [
+            ("e2e5", MoveError::Illegal("e2e5".to_string())),
+            // Rook jumping over its own pawn
+            ("a1a3", MoveError::Illegal("a1a3".to_string())),
+            // Empty square and the opponent's piece
+            ("e4e5", MoveError::Illegal("e4e5".to_string())),
+            ("e7e5", MoveError::Illegal("e7e5".to_string())),
+            ("e2", MoveError::Malformed("e2".to_string())),
+            ("e2e9", MoveError::Malformed("e2e9".to_string())),
+            ("e7e8k", MoveError::Malformed("e7e8k".to_string())),
+        ];
+        for (mov, error) in cases.iter() {
+            let mut board = Board::new(start);
+            assert_eq!(board.try_move(mov), Err(error.clone()), "{}", mov);
+            assert_eq!(board.to_string(), start);
+        }
+
+        let mut board = Board::new(start);
+        assert_eq!(board.try_move("e2e4"), Ok(()));
+        assert_eq!(
+            board.to_string(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+
+        // Pinned piece and promotion letter in either case
+        let mut board = Board::new("7k/1P2r3/8/8/8/8/4R3/4K3 w - - 0 1");
+        assert_eq!(
+            board.try_move("e2d2"),
+            Err(MoveError::Illegal("e2d2".to_string()))
+        );
+        assert_eq!(board.try_move("b7b8N"), Ok(()));
+        assert_eq!(board.to_string(), "1N5k/4r3/8/8/8/8/4R3/4K3 b - - 0 1");
+    }
+}
@@ -0,0 +1,367 @@
+use crate::move_gen;
+use crate::search;
+use crate::tree::Tree;
+use crate::{UciGo, UciOption, UciValue};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Owns a fixed pool of search threads and hands each one the current go
+// command.  Worker 0 is the main thread and the only one to print bestmove.
+pub struct SearchController {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Signalled when a new job is posted or the pool shuts down
+    job_posted: Condvar,
+    // Signalled when a worker finishes its part of a job or a stop completes
+    job_done: Condvar,
+    // The flag search::search polls to know when to give up
+    searching: Arc<Mutex<bool>>,
+    // Set while a go ponder search waits for ponderhit
+    pondering: Arc<Mutex<bool>>,
+}
+
+struct State {
+    job: Option<Job>,
+    generation: u64,
+    busy: usize,
+    stopping: bool,
+    quit: bool,
+}
+
+#[derive(Clone)]
+struct Job {
+    tree: Arc<Tree>,
+    options: Vec<UciOption>,
+    go_cmd: UciGo,
+}
+
+// Marks a worker idle again even if its search panics, so stop never hangs
+struct BusyGuard<'a>(&'a Shared);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = match self.0.state.lock() {
+            Ok(s) => s,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.busy -= 1;
+        // Let go of the finished job's tree so position can reuse it
+        if state.busy == 0 {
+            state.job = None;
+        }
+        self.0.job_done.notify_all();
+    }
+}
+
+impl SearchController {
+    pub fn new(threads: usize) -> SearchController {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                job: None,
+                generation: 0,
+                busy: 0,
+                stopping: false,
+                quit: false,
+            }),
+            job_posted: Condvar::new(),
+            job_done: Condvar::new(),
+            searching: Arc::new(Mutex::new(false)),
+            pondering: Arc::new(Mutex::new(false)),
+        });
+
+        let workers = (0..threads.max(1))
+            .map(|id| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || worker(shared, id))
+            })
+            .collect();
+
+        SearchController { shared, workers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_searching(&self) -> bool {
+        self.shared.state.lock().unwrap().busy > 0
+    }
+
+    // Starts a search on every worker.  A search still running is stopped
+    // first so that it prints its own bestmove before the new one begins.
+    pub fn go(&mut self, tree: &Arc<Tree>, options: &[UciOption], go_cmd: UciGo) {
+        self.stop();
+
+        let threads = match options.iter().find(|&x| x.name == "Threads").unwrap().value {
+            UciValue::Spin {
+                value,
+                default: _,
+                min: _,
+                max: _,
+            } => value as usize,
+            _ => panic!("Threads UCI Option should be a Spin option!"),
+        };
+        if threads != self.threads() {
+            self.shutdown();
+            *self = SearchController::new(threads);
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        *self.shared.searching.lock().unwrap() = true;
+        *self.shared.pondering.lock().unwrap() = go_cmd.ponder;
+        state.job = Some(Job {
+            tree: Arc::clone(tree),
+            options: options.to_vec(),
+            go_cmd,
+        });
+        state.generation += 1;
+        state.busy = self.workers.len();
+        self.shared.job_posted.notify_all();
+    }
+
+    // Ends the current search and blocks until its bestmove has been printed
+    pub fn stop(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.stopping = true;
+        *self.shared.searching.lock().unwrap() = false;
+        while state.busy > 0 {
+            state = self.shared.job_done.wait(state).unwrap();
+        }
+        state.stopping = false;
+        state.job = None;
+        self.shared.job_done.notify_all();
+    }
+
+    // Blocks until a search that has stopped by itself lets go of its tree.
+    // A search still running is left alone.
+    pub fn wait_idle(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.busy > 0 && !*self.shared.searching.lock().unwrap() {
+            state = self.shared.job_done.wait(state).unwrap();
+        }
+    }
+
+    // The opponent played the expected move, so the ponder search carries on
+    // under the time controls it was given
+    pub fn ponderhit(&self) {
+        *self.shared.pondering.lock().unwrap() = false;
+    }
+
+    // Blocks while a stop is still winding down the workers
+    pub fn wait_for_stop(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.stopping {
+            state = self.shared.job_done.wait(state).unwrap();
+        }
+    }
+
+    // Stops any search and joins every worker thread
+    pub fn shutdown(&mut self) {
+        self.stop();
+        self.shared.state.lock().unwrap().quit = true;
+        self.shared.job_posted.notify_all();
+        for handle in self.workers.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+fn worker(shared: Arc<Shared>, id: usize) {
+    let mut seen = 0;
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap();
+            while !state.quit && state.generation == seen {
+                state = shared.job_posted.wait(state).unwrap();
+            }
+            if state.quit {
+                return;
+            }
+            seen = state.generation;
+            state.job.clone()
+        };
+
+        let _busy = BusyGuard(&shared);
+        if let Some(job) = job {
+            let tree = Arc::clone(&job.tree);
+            // A panicking search must not take its worker with it, or the
+            // pool would wait on it at every later stop
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                search::search(
+                    job.tree,
+                    job.options,
+                    Arc::clone(&shared.searching),
+                    Arc::clone(&shared.pondering),
+                    job.go_cmd,
+                    id == 0,
+                )
+            }));
+            if result.is_err() {
+                // Wind down the other workers and still answer the go
+                match shared.searching.lock() {
+                    Ok(mut s) => *s = false,
+                    Err(poisoned) => *poisoned.into_inner() = false,
+                }
+                if id == 0 {
+                    match move_gen::legal_moves(&tree.board).first() {
+                        Some(mov) => println!("bestmove {}", mov),
+                        None => println!("bestmove 0000"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::STARTPOS;
+
+    fn options(threads: i32) -> Vec<UciOption> {
+        let (mut options, _) = crate::initialize();
+        for option in options.iter_mut() {
+            if option.name == "Threads" {
+                option.value = UciValue::Spin {
+                    value: threads,
+                    default: 1,
+                    min: 1,
+                    max: 2048,
+                };
+            }
+        }
+        options
+    }
+
+    fn infinite() -> UciGo {
+        UciGo {
+            infinite: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn go_stop_lifecycle() {
+        let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
+        let mut controller = SearchController::new(1);
+
+        // Stopping an idle pool returns straight away
+        controller.stop();
+        assert!(!controller.is_searching());
+
+        // A second go replaces the first search on a resized pool
+        controller.go(&tree, &options(1), infinite());
+        assert!(controller.is_searching());
+        controller.go(&tree, &options(2), infinite());
+        assert_eq!(controller.threads(), 2);
+        assert!(controller.is_searching());
+
+        controller.stop();
+        controller.wait_for_stop();
+        assert!(!controller.is_searching());
+
+        // Searches that end by themselves leave the pool idle
+        controller.go(
+            &tree,
+            &options(2),
+            UciGo {
+                nodes: Some(200),
+                ..Default::default()
+            },
+        );
+        controller.wait_for_stop();
+        while controller.is_searching() {
+            thread::yield_now();
+        }
+
+        controller.shutdown();
+        assert_eq!(controller.threads(), 0);
+    }
+
+    #[test]
+    fn finished_search_releases_tree() {
+        let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
+        let mut controller = SearchController::new(4);
+        controller.go(
+            &tree,
+            &options(4),
+            UciGo {
+                nodes: Some(20000),
+                ..Default::default()
+            },
+        );
+
+        // As soon as the search has decided to stop, the way it is when
+        // bestmove is printed, waiting leaves the tree to its owner
+        while *controller.shared.searching.lock().unwrap() {
+            thread::yield_now();
+        }
+        controller.wait_idle();
+        assert!(!controller.is_searching());
+        assert_eq!(Arc::strong_count(&tree), 1);
+
+        controller.shutdown();
+    }
+
+    #[test]
+    fn panicked_search_keeps_workers() {
+        let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
+        let mut controller = SearchController::new(2);
+
+        // Every search panics looking for an option that is not there
+        let mut broken = options(2);
+        broken.retain(|x| x.name != "MCTS_Backup");
+        let nodes = UciGo {
+            nodes: Some(200),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            controller.go(&tree, &broken, nodes.clone());
+            controller.stop();
+            assert!(!controller.is_searching());
+        }
+
+        // The same workers still search once the options are sound
+        controller.go(&tree, &options(2), nodes);
+        controller.wait_for_stop();
+        while controller.is_searching() {
+            thread::yield_now();
+        }
+        assert_eq!(controller.threads(), 2);
+
+        controller.shutdown();
+    }
+
+    #[test]
+    fn ponder_until_ponderhit() {
+        let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
+        let mut controller = SearchController::new(1);
+
+        // The node limit only applies once the ponder search is hit
+        controller.go(
+            &tree,
+            &options(1),
+            UciGo {
+                nodes: Some(100),
+                ponder: true,
+                ..Default::default()
+            },
+        );
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(controller.is_searching());
+
+        controller.ponderhit();
+        while controller.is_searching() {
+            thread::yield_now();
+        }
+
+        controller.shutdown();
+    }
+}
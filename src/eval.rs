@@ -1,74 +1,74 @@
 use crate::board;
+use crate::magic;
 use crate::misc;
-use crate::move_gen;
 use crate::search::Ending;
 use std::cmp;
 
-const initiative: i32 = 30;
+const INITIATIVE: i32 = 30;
 
-const mg_pawn_table: [i32; 64] = [
+const MG_PAWN_TABLE: [i32; 64] = [
     0, 0, 0, 0, 0, 0, 0, 0, 98, 134, 61, 95, 68, 126, 34, -11, -6, 7, 26, 31, 65, 56, 25, -20, -14,
     13, 6, 21, 23, 12, 17, -23, -27, -2, -5, 12, 17, 6, 10, -25, -26, -4, -4, -10, 3, 3, 33, -12,
     -35, -1, -20, -23, -15, 24, 38, -22, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
-const eg_pawn_table: [i32; 64] = [
+const EG_PAWN_TABLE: [i32; 64] = [
     0, 0, 0, 0, 0, 0, 0, 0, 178, 173, 158, 134, 147, 132, 165, 187, 94, 100, 85, 67, 56, 53, 82,
     84, 32, 24, 13, 5, -2, 4, 17, 17, 13, 9, -3, -7, -7, -8, 3, -1, 4, 7, -6, 1, 0, -5, -1, -8, 13,
     8, 8, 10, 13, 0, 2, -7, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
 
-const mg_knight_table: [i32; 64] = [
+const MG_KNIGHT_TABLE: [i32; 64] = [
     -167, -89, -34, -49, 61, -97, -15, -107, -73, -41, 72, 36, 23, 62, 7, -17, -47, 60, 37, 65, 84,
     129, 73, 44, -9, 17, 19, 53, 37, 69, 18, 22, -13, 4, 16, 13, 28, 19, 21, -8, -23, -9, 12, 10,
     19, 17, 25, -16, -29, -53, -12, -3, -1, 18, -14, -19, -105, -21, -58, -33, -17, -28, -19, -23,
 ];
-const eg_knight_table: [i32; 64] = [
+const EG_KNIGHT_TABLE: [i32; 64] = [
     -58, -38, -13, -28, -31, -27, -63, -99, -25, -8, -25, -2, -9, -25, -24, -52, -24, -20, 10, 9,
     -1, -9, -19, -41, -17, 3, 22, 22, 22, 11, 8, -18, -18, -6, 16, 25, 16, 17, 4, -18, -23, -3, -1,
     15, 10, -3, -20, -22, -42, -20, -10, -5, -2, -20, -23, -44, -29, -51, -23, -15, -22, -18, -50,
     -64,
 ];
 
-const mg_bishop_table: [i32; 64] = [
+const MG_BISHOP_TABLE: [i32; 64] = [
     -29, 4, -82, -37, -25, -42, 7, -8, -26, 16, -18, -13, 30, 59, 18, -47, -16, 37, 43, 40, 35, 50,
     37, -2, -4, 5, 19, 50, 37, 37, 7, -2, -6, 13, 13, 26, 34, 12, 10, 4, 0, 15, 15, 15, 14, 27, 18,
     10, 4, 15, 16, 0, 7, 21, 33, 1, -33, -3, -14, -21, -13, -12, -39, -21,
 ];
-const eg_bishop_table: [i32; 64] = [
+const EG_BISHOP_TABLE: [i32; 64] = [
     -14, -21, -11, -8, -7, -9, -17, -24, -8, -4, 7, -12, -3, -13, -4, -14, 2, -8, 0, -1, -2, 6, 0,
     4, -3, 9, 12, 9, 14, 10, 3, 2, -6, 3, 13, 19, 7, 10, -3, -9, -12, -3, 8, 10, 13, 3, -7, -15,
     -14, -18, -7, -1, 4, -9, -15, -27, -23, -9, -23, -5, -9, -16, -5, -17,
 ];
 
-const mg_rook_table: [i32; 64] = [
+const MG_ROOK_TABLE: [i32; 64] = [
     32, 42, 32, 51, 63, 9, 31, 43, 27, 32, 58, 62, 80, 67, 26, 44, -5, 19, 26, 36, 17, 45, 61, 16,
     -24, -11, 7, 26, 24, 35, -8, -20, -36, -26, -12, -1, 9, -7, 6, -23, -45, -25, -16, -17, 3, 0,
     -5, -33, -44, -16, -20, -9, -1, 11, -6, -71, -19, -13, 1, 17, 16, 7, -37, -26,
 ];
-const eg_rook_table: [i32; 64] = [
+const EG_ROOK_TABLE: [i32; 64] = [
     13, 10, 18, 15, 12, 12, 8, 5, 11, 13, 13, 11, -3, 3, 8, 3, 7, 7, 7, 5, 4, -3, -5, -3, 4, 3, 13,
     1, 2, 1, -1, 2, 3, 5, 8, 4, -5, -6, -8, -11, -4, 0, -5, -1, -7, -12, -8, -16, -6, -6, 0, 2, -9,
     -9, -11, -3, -9, 2, 3, -1, -5, -13, 4, -20,
 ];
 
-const mg_queen_table: [i32; 64] = [
+const MG_QUEEN_TABLE: [i32; 64] = [
     -28, 0, 29, 12, 59, 44, 43, 45, -24, -39, -5, 1, -16, 57, 28, 54, -13, -17, 7, 8, 29, 56, 47,
     57, -27, -27, -16, -16, -1, 17, -2, 1, -9, -26, -9, -10, -2, -4, 3, -3, -14, 2, -11, -2, -5, 2,
     14, 5, -35, -8, 11, 2, 8, 15, -3, 1, -1, -18, -9, 10, -15, -25, -31, -50,
 ];
-const eg_queen_table: [i32; 64] = [
+const EG_QUEEN_TABLE: [i32; 64] = [
     -9, 22, 22, 27, 27, 19, 10, 20, -17, 20, 32, 41, 58, 25, 30, 0, -20, 6, 9, 49, 47, 35, 19, 9,
     3, 22, 24, 45, 57, 40, 57, 36, -18, 28, 19, 47, 31, 34, 39, 23, -16, -27, 15, 6, 9, 17, 10, 5,
     -22, -23, -30, -16, -16, -23, -36, -32, -33, -28, -22, -43, -5, -32, -20, -41,
 ];
 
-const mg_king_table: [i32; 64] = [
+const MG_KING_TABLE: [i32; 64] = [
     -65, 23, 16, -15, -56, -34, 2, 13, 29, -1, -20, -7, -8, -4, -38, -29, -9, 24, 2, -16, -20, 6,
     22, -22, -17, -20, -12, -27, -30, -25, -14, -36, -49, -1, -27, -39, -46, -44, -33, -51, -14,
     -14, -22, -46, -44, -30, -15, -27, 1, 7, -8, -64, -43, -16, 9, 8, -15, 36, 12, -54, 8, -28, 24,
     14,
 ];
-const eg_king_table: [i32; 64] = [
+const EG_KING_TABLE: [i32; 64] = [
     -74, -35, -18, -18, -11, 15, 4, -17, -12, 17, 14, 17, 17, 38, 23, 11, 10, 17, 23, 15, 20, 45,
     44, 13, -8, 22, 24, 27, 26, 33, 26, 3, -18, -4, 21, 24, 27, 23, 9, -11, -19, -3, 11, 21, 23,
     16, 7, -9, -27, -11, 4, 13, 14, 4, -5, -17, -53, -34, -21, -11, -28, -14, -24, -43,
@@ -85,9 +85,17 @@ const EG_R_VAL: u32 = 512;
 const MG_Q_VAL: u32 = 1025;
 const EG_Q_VAL: u32 = 936;
 
+const LIGHT_SQUARES_BB: u64 = 0x55aa55aa55aa55aa;
+const A_FILE_BB: u64 = 0x0101010101010101;
+
 pub fn evaluate(board: &board::Board) -> (Option<Ending>, f32) {
     let end = eval_ending(board);
 
+    // Nobody can win a dead position, don't let the material term say otherwise
+    if is_dead_position(board) {
+        return (end, 0.5);
+    }
+
     let w_mg_mat = (board.w_p_bb.count_ones() * MG_P_VAL
         + board.w_n_bb.count_ones() * MG_N_VAL
         + board.w_b_bb.count_ones() * MG_B_VAL
@@ -135,9 +143,9 @@ pub fn evaluate(board: &board::Board) -> (Option<Ending>, f32) {
 
     // Add the initiative
     eval += if board.is_w_move {
-        initiative
+        INITIATIVE
     } else {
-        -initiative
+        -INITIATIVE
     };
 
     /*
@@ -162,13 +170,76 @@ pub fn evaluate(board: &board::Board) -> (Option<Ending>, f32) {
 
 fn eval_ending(board: &board::Board) -> Option<Ending> {
     // Check drawing conditions
-    if board.halfmove_clock >= 50 {
+    if board.halfmove_clock >= 50 || is_dead_position(board) {
         Some(Ending::Draw)
     } else {
         None
     }
 }
 
+// Positions where neither side can ever checkmate, judged by material alone:
+// K vs K, K+minor vs K, and kings with bishops that all share a square color
+fn is_dead_position(board: &board::Board) -> bool {
+    if board.w_p_bb | board.b_p_bb | board.w_r_bb | board.b_r_bb | board.w_q_bb | board.b_q_bb > 0 {
+        return false;
+    }
+
+    let knights = board.w_n_bb | board.b_n_bb;
+    let bishops = board.w_b_bb | board.b_b_bb;
+    if knights.count_ones() + bishops.count_ones() <= 1 {
+        return true;
+    }
+
+    knights == 0 && (bishops & LIGHT_SQUARES_BB == 0 || bishops & !LIGHT_SQUARES_BB == 0)
+}
+
+// How sharp a position is, from 0 for quiet to 1 for wild.  Averages three
+// terms that each saturate: open and half open files, enemy pieces crowding
+// either king, and piece types the two sides hold in different numbers.
+pub fn sharpness(board: &board::Board) -> f32 {
+    let mut files: f32 = 0.0;
+    for col in 0..8 {
+        let file = A_FILE_BB << col;
+        match (board.w_p_bb & file > 0, board.b_p_bb & file > 0) {
+            (false, false) => files += 1.0,
+            (true, false) | (false, true) => files += 0.5,
+            (true, true) => (),
+        }
+    }
+
+    let w_pieces = board.w_n_bb | board.w_b_bb | board.w_r_bb | board.w_q_bb;
+    let b_pieces = board.b_n_bb | board.b_b_bb | board.b_r_bb | board.b_q_bb;
+    let attackers = (king_zone(board.w_k_bb) & b_pieces).count_ones()
+        + (king_zone(board.b_k_bb) & w_pieces).count_ones();
+
+    let imbalances = [
+        (board.w_n_bb, board.b_n_bb),
+        (board.w_b_bb, board.b_b_bb),
+        (board.w_r_bb, board.b_r_bb),
+        (board.w_q_bb, board.b_q_bb),
+    ]
+    .iter()
+    .filter(|(w, b)| w.count_ones() != b.count_ones())
+    .count();
+
+    ((files / 4.0).min(1.0)
+        + (attackers as f32 / 4.0).min(1.0)
+        + (imbalances as f32 / 2.0).min(1.0))
+        / 3.0
+}
+
+// Squares within two king steps of the king
+fn king_zone(k_bb: u64) -> u64 {
+    let near = magic::king_collisions[k_bb.trailing_zeros() as usize];
+    let mut zone = near;
+    let mut bb = near;
+    while bb > 0 {
+        zone |= magic::king_collisions[bb.trailing_zeros() as usize];
+        bb &= bb - 1;
+    }
+    zone
+}
+
 fn pesto_score(board: &board::Board, is_w_move: bool, is_mg_phase: bool) -> i32 {
     let bbs = if is_w_move {
         [
@@ -192,21 +263,21 @@ fn pesto_score(board: &board::Board, is_w_move: bool, is_mg_phase: bool) -> i32
 
     let pestos = if is_mg_phase {
         [
-            mg_pawn_table,
-            mg_knight_table,
-            mg_bishop_table,
-            mg_rook_table,
-            mg_queen_table,
-            mg_king_table,
+            MG_PAWN_TABLE,
+            MG_KNIGHT_TABLE,
+            MG_BISHOP_TABLE,
+            MG_ROOK_TABLE,
+            MG_QUEEN_TABLE,
+            MG_KING_TABLE,
         ]
     } else {
         [
-            eg_pawn_table,
-            eg_knight_table,
-            eg_bishop_table,
-            eg_rook_table,
-            eg_queen_table,
-            eg_king_table,
+            EG_PAWN_TABLE,
+            EG_KNIGHT_TABLE,
+            EG_BISHOP_TABLE,
+            EG_ROOK_TABLE,
+            EG_QUEEN_TABLE,
+            EG_KING_TABLE,
         ]
     };
 
@@ -237,3 +308,59 @@ fn pesto_score(board: &board::Board, is_w_move: bool, is_mg_phase: bool) -> i32
     }
     score
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_positions() {
+        let positions = [
+            // K vs K
+            ("8/8/4k3/8/8/3K4/8/8 w - - 0 1", true),
+            // K+B vs K
+            ("8/8/4k3/8/8/3KB3/8/8 w - - 0 1", true),
+            ("8/8/4kb2/8/8/3K4/8/8 b - - 0 1", true),
+            // K+N vs K
+            ("8/8/4k3/8/8/3KN3/8/8 w - - 0 1", true),
+            ("8/8/4kn2/8/8/3K4/8/8 w - - 0 1", true),
+            // K+B vs K+B, bishops on the same color
+            ("8/8/4kb2/8/8/3KB3/8/8 w - - 0 1", true),
+            ("2b5/8/4k3/8/8/3K4/8/5B2 w - - 0 1", true),
+            // Any number of bishops sharing one color
+            ("8/8/4k3/8/8/3KB3/8/2B5 w - - 0 1", true),
+            ("1b6/b7/4k3/8/8/3KB3/8/8 b - - 0 1", true),
+            // K+B vs K+B, bishops on opposite colors
+            ("8/8/4k1b1/8/8/3KB3/8/8 w - - 0 1", false),
+            // Mate is possible in these
+            ("8/8/4k3/8/8/3KNN2/8/8 w - - 0 1", false),
+            ("8/8/4kn2/8/8/3KN3/8/8 w - - 0 1", false),
+            ("8/8/4kn2/8/8/3KB3/8/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/3KBB2/8/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/3KP3/8/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/3K4/8/R7 w - - 0 1", false),
+            ("8/8/4k3/8/8/3K4/8/Q7 w - - 0 1", false),
+            ("8/8/4kp2/8/8/3KB3/8/8 w - - 0 1", false),
+        ];
+
+        for (fen, dead) in positions.iter() {
+            let board = board::Board::new(fen);
+            assert_eq!(is_dead_position(&board), *dead, "{}", fen);
+            let (end, eval) = evaluate(&board);
+            assert_eq!(matches!(end, Some(Ending::Draw)), *dead, "{}", fen);
+            if *dead {
+                assert_eq!(eval, 0.5, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn sharpness_ranks_positions() {
+        let quiet = board::Board::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(sharpness(&quiet), 0.0);
+
+        // Open files, a knight for three pawns and pieces swarming the black king
+        let sharp = board::Board::new("r4rk1/5pp1/7Q/6N1/8/8/PP3PPP/R5K1 b - - 0 1");
+        assert!(sharpness(&sharp) > 0.6, "{}", sharpness(&sharp));
+    }
+}
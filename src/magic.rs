@@ -0,0 +1,132 @@
+#![allow(dead_code, non_upper_case_globals)]
+
+// Attack and magic bitboard tables, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/magic.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::misc;
+    use crate::move_gen;
+
+    const OCCUPANCY_SAMPLES: usize = 2000;
+
+    fn ray_walk(pos: i32, directions: &[(i32, i32)], occupied: u64) -> u64 {
+        let mut bb = 0;
+        for (dc, dr) in directions.iter() {
+            let mut col = pos % 8 + dc;
+            let mut row = pos / 8 + dr;
+            while (0..8).contains(&col) && (0..8).contains(&row) {
+                let sq = 0x1 << (row * 8 + col);
+                bb |= sq;
+                if occupied & sq > 0 {
+                    break;
+                }
+                col += dc;
+                row += dr;
+            }
+        }
+        bb
+    }
+
+    fn step_walk(pos: i32, deltas: &[(i32, i32)]) -> u64 {
+        ray_walk(pos, deltas, u64::MAX)
+    }
+
+    fn sample_occupancies() -> Vec<u64> {
+        let mut state = 0x5eed;
+        let mut samples = vec![0, u64::MAX];
+        for _ in 0..OCCUPANCY_SAMPLES {
+            let (hi, s) = misc::spcg32(&state);
+            let (lo, s) = misc::spcg32(&s);
+            let (mask, s) = misc::spcg32(&s);
+            state = s;
+            // Thin the board out so long sliding rays get exercised too
+            let bb = ((hi as u64) << 32) | lo as u64;
+            samples.push(bb & (((mask as u64) << 32) | mask as u64));
+            samples.push(bb);
+        }
+        samples
+    }
+
+    #[test]
+    fn step_tables() {
+        let knight = [
+            (1, 2),
+            (2, 1),
+            (2, -1),
+            (1, -2),
+            (-1, -2),
+            (-2, -1),
+            (-2, 1),
+            (-1, 2),
+        ];
+        let king = [
+            (0, 1),
+            (1, 1),
+            (1, 0),
+            (1, -1),
+            (0, -1),
+            (-1, -1),
+            (-1, 0),
+            (-1, 1),
+        ];
+        for pos in 0..64 {
+            let i = pos as usize;
+            assert_eq!(knight_collisions[i], step_walk(pos, &knight), "{}", pos);
+            assert_eq!(king_collisions[i], step_walk(pos, &king), "{}", pos);
+            assert_eq!(
+                w_pawn_attack_collisions[i],
+                step_walk(pos, &[(1, 1), (-1, 1)]),
+                "{}",
+                pos
+            );
+            assert_eq!(
+                b_pawn_attack_collisions[i],
+                step_walk(pos, &[(1, -1), (-1, -1)]),
+                "{}",
+                pos
+            );
+            assert_eq!(w_pawn_move_collisions[i], step_walk(pos, &[(0, 1)]));
+            assert_eq!(b_pawn_move_collisions[i], step_walk(pos, &[(0, -1)]));
+        }
+    }
+
+    #[test]
+    fn rook_magic_move_sets_match_ray_walk() {
+        let directions = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+        let samples = sample_occupancies();
+        for pos in 0..64 {
+            let bb = 0x1 << pos;
+            for occupied in samples.iter() {
+                let occupied = occupied & !bb;
+                assert_eq!(
+                    move_gen::solo_rook_moves(bb, 0, occupied),
+                    ray_walk(pos, &directions, occupied),
+                    "Rook on {} with occupancy {:#x}",
+                    pos,
+                    occupied
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bishop_magic_move_sets_match_ray_walk() {
+        let directions = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+        let samples = sample_occupancies();
+        for pos in 0..64 {
+            let bb = 0x1 << pos;
+            for occupied in samples.iter() {
+                let occupied = occupied & !bb;
+                assert_eq!(
+                    move_gen::solo_bishop_moves(bb, 0, occupied),
+                    ray_walk(pos, &directions, occupied),
+                    "Bishop on {} with occupancy {:#x}",
+                    pos,
+                    occupied
+                );
+            }
+        }
+    }
+}
@@ -1,15 +1,24 @@
+mod bench;
 mod board;
+mod controller;
 mod eval;
 mod magic;
 mod misc;
 mod move_gen;
+mod perft;
+mod policy;
+mod quiesce;
 mod search;
+mod time_manager;
+mod tree;
+mod zobrist;
 
 use board::Board;
-use search::Node;
+use controller::SearchController;
 use std::io;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::Arc;
+use std::time::Instant;
+use tree::Tree;
 
 #[derive(Clone)]
 pub struct UciOption {
@@ -30,27 +39,29 @@ pub enum UciValue {
         min: i32,
         max: i32,
     },
+    Combo {
+        value: String,
+        default: String,
+        vars: Vec<String>,
+    },
 }
 
-#[derive(Clone)]
-pub enum UciGo {
-    Time {
-        wtime: Option<u32>,
-        btime: Option<u32>,
-        winc: Option<u32>,
-        binc: Option<u32>,
-        movestogo: Option<u32>,
-    },
-    Depth {
-        plies: u32,
-    },
-    Nodes {
-        count: u32,
-    },
-    Movetime {
-        mseconds: u32,
-    },
-    Infinite,
+// Every limit a go command can carry.  Any combination may be given and the
+// search ends on whichever is reached first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UciGo {
+    pub wtime: Option<u32>,
+    pub btime: Option<u32>,
+    pub winc: Option<u32>,
+    pub binc: Option<u32>,
+    pub movestogo: Option<u32>,
+    pub depth: Option<u32>,
+    pub nodes: Option<u32>,
+    pub movetime: Option<u32>,
+    pub mate: Option<u32>,
+    pub infinite: bool,
+    pub ponder: bool,
+    pub searchmoves: Vec<String>,
 }
 
 enum PositionState {
@@ -60,20 +71,26 @@ enum PositionState {
     Moves,
 }
 
-enum GoState {
-    WTime,
-    BTime,
-    WInc,
-    BInc,
-    Movestogo,
-    Initial,
-}
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+];
 
 const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
 fn main() {
-    let (mut options, mut root) = initialize();
-    let searching = Arc::new(Mutex::new(false));
+    let (mut options, mut tree) = initialize();
+    let mut controller = SearchController::new(1);
 
     println!("Ragnarook 0.1 by Kyle Forrester");
 
@@ -81,110 +98,170 @@ fn main() {
         let input = tokenize_stdin();
         match input[0].as_str() {
             "uci" => uci_uci(&options),
-            "isready" => uci_isready(),
+            "isready" => uci_isready(&controller),
             "setoption" => uci_setoption(&mut options, input),
-            "ucinewgame" => root = uci_newgame(),
-            "position" => root = uci_position(root, input),
-            "go" => uci_go(&root, &options, &searching, input),
-            "stop" => uci_stop(&searching),
-            "quit" => uci_quit(),
-            "fen" => print_fen(&root),
+            "ucinewgame" => tree = uci_newgame(tree),
+            "position" => {
+                // A search that just printed bestmove may still hold the tree
+                controller.wait_idle();
+                tree = uci_position(tree, input)
+            }
+            "go" => uci_go(&mut controller, &tree, &options, input),
+            "stop" => controller.stop(),
+            "ponderhit" => controller.ponderhit(),
+            "quit" => uci_quit(&mut controller),
+            "fen" => print_fen(&tree),
             "static_eval" => static_eval(input),
+            "perft" => perft(&tree, input, false),
+            "divide" => perft(&tree, input, true),
+            "bench" => bench(&options, input),
             _ => println!("Invalid command: {}", input[0]),
         }
     }
 }
 
-fn initialize() -> (Vec<UciOption>, Arc<Node>) {
-    let mut options = Vec::new();
-    options.push(UciOption {
-        name: String::from("Threads"),
-        value: UciValue::Spin {
-            value: 1,
-            default: 1,
-            min: 1,
-            max: 2048,
+fn initialize() -> (Vec<UciOption>, Arc<Tree>) {
+    let options = vec![
+        UciOption {
+            name: String::from("Threads"),
+            value: UciValue::Spin {
+                value: 1,
+                default: 1,
+                min: 1,
+                max: 2048,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("MultiPV"),
-        value: UciValue::Spin {
-            value: 1,
-            default: 1,
-            min: 1,
-            max: 256,
+        UciOption {
+            name: String::from("MultiPV"),
+            value: UciValue::Spin {
+                value: 1,
+                default: 1,
+                min: 1,
+                max: 256,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("Move_Overhead"),
-        value: UciValue::Spin {
-            value: 100,
-            default: 100,
-            min: 10,
-            max: 5000,
+        UciOption {
+            name: String::from("Ponder"),
+            value: UciValue::Check {
+                value: false,
+                default: false,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("Move_Speed"),
-        value: UciValue::Spin {
-            value: 50,
-            default: 50,
-            min: 1,
-            max: 100,
+        UciOption {
+            name: String::from("Move_Overhead"),
+            value: UciValue::Spin {
+                value: 100,
+                default: 100,
+                min: 10,
+                max: 5000,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("MCTS_Explore"),
-        value: UciValue::Spin {
-            value: 50,
-            default: 50,
-            min: 1,
-            max: 100,
+        UciOption {
+            name: String::from("Move_Speed"),
+            value: UciValue::Spin {
+                value: 50,
+                default: 50,
+                min: 1,
+                max: 100,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("MCTS_Hash"),
-        value: UciValue::Spin {
-            value: 4096,
-            default: 4096,
-            min: 16,
-            max: 32768,
+        UciOption {
+            name: String::from("MCTS_Explore"),
+            value: UciValue::Spin {
+                value: 50,
+                default: 50,
+                min: 1,
+                max: 100,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("Skill"),
-        value: UciValue::Spin {
-            value: 100,
-            default: 100,
-            min: 1,
-            max: 100,
+        UciOption {
+            name: String::from("MCTS_Hash"),
+            value: UciValue::Spin {
+                value: 4096,
+                default: 4096,
+                min: 16,
+                max: 32768,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("Contempt"),
-        value: UciValue::Spin {
-            value: 0,
-            default: 0,
-            min: -100,
-            max: 100,
+        UciOption {
+            name: String::from("MCTS_Quiescence"),
+            value: UciValue::Spin {
+                value: 0,
+                default: 0,
+                min: 0,
+                max: 32,
+            },
         },
-    });
-    options.push(UciOption {
-        name: String::from("Dynamism"),
-        value: UciValue::Spin {
-            value: 50,
-            default: 50,
-            min: 1,
-            max: 100,
+        UciOption {
+            name: String::from("MCTS_Backup"),
+            value: UciValue::Combo {
+                value: String::from("Minimax"),
+                default: String::from("Minimax"),
+                vars: vec![
+                    String::from("Minimax"),
+                    String::from("Mean"),
+                    String::from("PowerMean"),
+                ],
+            },
         },
-    });
+        UciOption {
+            name: String::from("MCTS_Transpositions"),
+            value: UciValue::Check {
+                value: false,
+                default: false,
+            },
+        },
+        UciOption {
+            name: String::from("Skill"),
+            value: UciValue::Spin {
+                value: 100,
+                default: 100,
+                min: 1,
+                max: 100,
+            },
+        },
+        UciOption {
+            name: String::from("UCI_LimitStrength"),
+            value: UciValue::Check {
+                value: false,
+                default: false,
+            },
+        },
+        UciOption {
+            name: String::from("UCI_Elo"),
+            value: UciValue::Spin {
+                value: misc::MAX_ELO,
+                default: misc::MAX_ELO,
+                min: misc::MIN_ELO,
+                max: misc::MAX_ELO,
+            },
+        },
+        UciOption {
+            name: String::from("Contempt"),
+            value: UciValue::Spin {
+                value: 0,
+                default: 0,
+                min: -100,
+                max: 100,
+            },
+        },
+        UciOption {
+            name: String::from("Dynamism"),
+            value: UciValue::Spin {
+                value: 50,
+                default: 50,
+                min: 1,
+                max: 100,
+            },
+        },
+    ];
 
-    let root = Arc::new(Node::new(Board::new(STARTPOS)));
+    let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
 
-    (options, root)
+    (options, tree)
 }
 
-fn uci_uci(options: &Vec<UciOption>) {
+fn uci_uci(options: &[UciOption]) {
     println!("id name Ragnarook 0.1.0");
     println!("id author Kyle Forrester");
 
@@ -203,17 +280,31 @@ fn uci_uci(options: &Vec<UciOption>) {
                 "option name {} type spin default {} min {} max {}",
                 option.name, default, min, max
             ),
+            UciValue::Combo {
+                value: _,
+                ref default,
+                ref vars,
+            } => {
+                let vars: String = vars.iter().map(|v| format!(" var {}", v)).collect();
+                println!(
+                    "option name {} type combo default {}{}",
+                    option.name, default, vars
+                )
+            }
         }
     }
 
+    println!("info string MCTS_Quiescence is experimental and off by default. It searches captures this many plies deep at each leaf, which so far costs more in speed than it gains in strength.");
+    println!("info string Dynamism 50 is neutral. Higher values prefer sharp positions with open files, pieces near the kings and material imbalances. Lower values prefer quiet ones.");
     println!("uciok");
 }
 
-fn uci_isready() {
+fn uci_isready(controller: &SearchController) {
+    controller.wait_for_stop();
     println!("readyok");
 }
 
-fn uci_setoption(options: &mut Vec<UciOption>, input: Vec<String>) {
+fn uci_setoption(options: &mut [UciOption], input: Vec<String>) {
     if input[1] != "name" || input[3] != "value" {
         println!("Unrecognized UCI setoption command");
         return;
@@ -241,24 +332,34 @@ fn uci_setoption(options: &mut Vec<UciOption>, input: Vec<String>) {
         UciValue::Spin {
             ref mut value,
             default: _,
-            min: _,
-            max: _,
-        } => match input[4].trim().parse() {
-            Ok(v) => *value = v,
+            min,
+            max,
+        } => match input[4].trim().parse::<i32>() {
+            // Out of range values get the nearest one the option allows
+            Ok(v) => *value = v.clamp(min, max),
             Err(_) => println!("Unrecognized UCI setoption command"),
         },
+        UciValue::Combo {
+            ref mut value,
+            default: _,
+            ref vars,
+        } => match vars.iter().find(|v| v.eq_ignore_ascii_case(&input[4])) {
+            Some(v) => *value = v.clone(),
+            None => println!("Unrecognized UCI setoption command"),
+        },
         _ => println!("Internal Error. UCI property not initialized appropriately."),
     }
 }
 
-fn uci_newgame() -> Arc<Node> {
-    return Arc::new(Node::new(Board::new(STARTPOS)));
+fn uci_newgame(tree: Arc<Tree>) -> Arc<Tree> {
+    Tree::drop_in_background(tree);
+    Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()))
 }
 
-fn uci_position(root: Arc<Node>, input: Vec<String>) -> Arc<Node> {
+fn uci_position(mut tree: Arc<Tree>, input: Vec<String>) -> Arc<Tree> {
     if input.len() <= 1 {
         println!("Unrecognized UCI position command");
-        return root;
+        return tree;
     }
     //Build out what the board should look like
     let mut pos_state = PositionState::Initial;
@@ -280,11 +381,11 @@ fn uci_position(root: Arc<Node>, input: Vec<String>) -> Arc<Node> {
             _ => match pos_state {
                 PositionState::Initial => {
                     println!("Unexpected parameter {}!", token);
-                    return root;
+                    return tree;
                 }
                 PositionState::StartPos => {
                     println!("Unexpected parameter {}!", token);
-                    return root;
+                    return tree;
                 }
                 PositionState::Fen => {
                     fen_accumulator.push(token.to_string());
@@ -296,155 +397,102 @@ fn uci_position(root: Arc<Node>, input: Vec<String>) -> Arc<Node> {
         }
     }
 
-    if fen_accumulator.len() > 0 {
+    if !fen_accumulator.is_empty() {
         fen = fen_accumulator.join(" ");
     }
 
-    //Create the board position
-    let mut board = Board::new(&fen);
-    for mov in moves_accumulator.iter() {
-        board.do_move(mov);
-    }
-
-    //Set the root node to the current node, child node, or grandchild with matching board
-    //If no one matches the board, start a new root node with the correct board
-    if root.board.eq(&board) {
-        return root;
-    }
-    let children = root.children.read().unwrap();
-    for child in children.iter() {
-        if child.board.eq(&board) {
-            return Arc::clone(child);
+    //Create the board position, remembering every earlier position for repetitions
+    let mut board = match Board::from_fen(&fen) {
+        Ok(b) => b,
+        Err(e) => {
+            println!("Invalid fen {}: {}", fen, e);
+            return tree;
         }
-        let grandchildren = child.children.read().unwrap();
-        for grandchild in grandchildren.iter() {
-            if grandchild.board.eq(&board) {
-                return Arc::clone(grandchild);
-            }
+    };
+    let mut positions = vec![board];
+    for mov in moves_accumulator.iter() {
+        if let Err(e) = board.try_move(mov) {
+            println!("Invalid moves command at {}: {}", mov, e);
+            return tree;
         }
+        positions.push(board);
+    }
+    let history: Vec<u64> = positions[..positions.len() - 1]
+        .iter()
+        .map(|b| b.hash)
+        .collect();
+
+    //Keep the tree when the new root is already in it and no search still
+    //holds it, otherwise start a new tree with the correct board
+    tree.wait_for_free();
+    let reused = match Arc::get_mut(&mut tree) {
+        Some(t) => t.reroot(&positions, history.clone()),
+        None => false,
+    };
+    if reused {
+        //The old tree is freed while the next search runs
+        Tree::free_detached_in_background(&tree);
+        tree
+    } else {
+        Tree::drop_in_background(tree);
+        Arc::new(Tree::new(board, history))
     }
-
-    //None were found, start a new root node
-    Arc::new(Node::new(board))
 }
 
 fn uci_go(
-    root: &Arc<Node>,
-    options: &Vec<UciOption>,
-    searching: &Arc<Mutex<bool>>,
+    controller: &mut SearchController,
+    tree: &Arc<Tree>,
+    options: &[UciOption],
     input: Vec<String>,
 ) {
-    let mut s = searching.lock().unwrap();
-    *s = true;
-
-    let go_cmd = parse_go_command(input);
-    let new_root = Arc::clone(root);
-    let new_options = options.clone();
-    let new_searching = Arc::clone(searching);
-    let new_go_cmd = go_cmd.clone();
-
-    thread::spawn(move || {
-        search::search(new_root, new_options, new_searching, new_go_cmd, true);
-    });
-
-    let threads = match options.iter().find(|&x| x.name == "Threads").unwrap().value {
-        UciValue::Spin {
-            value,
-            default: _,
-            min: _,
-            max: _,
-        } => value,
-        _ => panic!("Threads UCI Option should be a Spin option!"),
-    };
-
-    for _i in 0..threads - 1 {
-        let new_root = Arc::clone(root);
-        let new_options = options.clone();
-        let new_searching = Arc::clone(searching);
-        let new_go_cmd = go_cmd.clone();
-        thread::spawn(move || {
-            search::search(new_root, new_options, new_searching, new_go_cmd, false);
-        });
+    match parse_go_command(input) {
+        Ok(go_cmd) => controller.go(tree, options, go_cmd),
+        Err(e) => println!("Invalid go command: {}", e),
     }
 }
 
-fn parse_go_command(input: Vec<String>) -> UciGo {
-    match input[1].as_str() {
-        "wtime" | "btime" | "winc" | "binc" | "movestogo" => parse_go_time(input),
-        "depth" => parse_go_depth(input),
-        "nodes" => parse_go_nodes(input),
-        "movetime" => parse_go_movetime(input),
-        "infinite" => parse_go_infinite(input),
-        _ => panic!("UCI go command {} not recognized", input[1].as_str()),
-    }
-}
-
-fn parse_go_time(input: Vec<String>) -> UciGo {
-    let mut wtime = Option::None;
-    let mut btime = Option::None;
-    let mut winc = Option::None;
-    let mut binc = Option::None;
-    let mut movestogo = Option::None;
-    let mut state = GoState::Initial;
-    for word in &input[1..] {
+fn parse_go_command(input: Vec<String>) -> Result<UciGo, String> {
+    let mut go_cmd = UciGo::default();
+    let mut words = input[1..].iter().peekable();
+    while let Some(word) = words.next() {
         match word.as_str() {
-            "wtime" => state = GoState::WTime,
-            "btime" => state = GoState::BTime,
-            "winc" => state = GoState::WInc,
-            "binc" => state = GoState::BInc,
-            "movestogo" => state = GoState::Movestogo,
-            _ => match state {
-                GoState::WTime => wtime = Some(word.parse().unwrap()),
-                GoState::BTime => btime = Some(word.parse().unwrap()),
-                GoState::WInc => winc = Some(word.parse().unwrap()),
-                GoState::BInc => binc = Some(word.parse().unwrap()),
-                GoState::Movestogo => movestogo = Some(word.parse().unwrap()),
-                GoState::Initial => panic!("Go command requires arguments!"),
-            },
+            "wtime" => go_cmd.wtime = Some(parse_go_value(word, words.next())?),
+            "btime" => go_cmd.btime = Some(parse_go_value(word, words.next())?),
+            "winc" => go_cmd.winc = Some(parse_go_value(word, words.next())?),
+            "binc" => go_cmd.binc = Some(parse_go_value(word, words.next())?),
+            "movestogo" => go_cmd.movestogo = Some(parse_go_value(word, words.next())?),
+            "depth" => go_cmd.depth = Some(parse_go_value(word, words.next())?),
+            "nodes" => go_cmd.nodes = Some(parse_go_value(word, words.next())?),
+            "movetime" => go_cmd.movetime = Some(parse_go_value(word, words.next())?),
+            "mate" => go_cmd.mate = Some(parse_go_value(word, words.next())?),
+            "infinite" => go_cmd.infinite = true,
+            "ponder" => go_cmd.ponder = true,
+            "searchmoves" => {
+                // Moves run until the next keyword or the end of the command
+                while let Some(mov) = words.next_if(|w| !GO_KEYWORDS.contains(&w.as_str())) {
+                    go_cmd.searchmoves.push(mov.to_lowercase());
+                }
+            }
+            _ => return Err(format!("unrecognized token {}", word)),
         }
     }
-
-    let go_enum = UciGo::Time {
-        wtime: wtime,
-        btime: btime,
-        winc: winc,
-        binc: binc,
-        movestogo: movestogo,
-    };
-    if wtime.is_none() && btime.is_none() {
-        panic!("Go command with times must implement either wtime or btime!");
-    }
-    go_enum
+    Ok(go_cmd)
 }
 
-fn parse_go_depth(input: Vec<String>) -> UciGo {
-    UciGo::Depth {
-        plies: input[2].parse().unwrap(),
-    }
-}
-
-fn parse_go_nodes(input: Vec<String>) -> UciGo {
-    UciGo::Nodes {
-        count: input[2].parse().unwrap(),
-    }
-}
-
-fn parse_go_movetime(input: Vec<String>) -> UciGo {
-    UciGo::Movetime {
-        mseconds: input[2].parse().unwrap(),
+// GUIs sometimes send negative clocks once a flag has fallen, treat them as 0
+fn parse_go_value(name: &str, value: Option<&String>) -> Result<u32, String> {
+    let value = match value {
+        Some(v) => v,
+        None => return Err(format!("{} requires a value", name)),
+    };
+    match value.parse::<i64>() {
+        Ok(v) => Ok(v.clamp(0, i64::from(u32::MAX)) as u32),
+        Err(_) => Err(format!("{} value {} is not a number", name, value)),
     }
 }
 
-fn parse_go_infinite(_input: Vec<String>) -> UciGo {
-    UciGo::Infinite
-}
-
-fn uci_stop(searching: &Mutex<bool>) {
-    let mut s = searching.lock().unwrap();
-    *s = false;
-}
-
-fn uci_quit() {
+fn uci_quit(controller: &mut SearchController) {
+    controller.shutdown();
     std::process::exit(0);
 }
 
@@ -453,41 +501,172 @@ fn tokenize_stdin() -> Vec<String> {
     io::stdin()
         .read_line(&mut input)
         .expect("Error reading from stdin");
-    input
-        .split_ascii_whitespace()
-        .map(|x| String::from(x))
-        .collect()
+    input.split_ascii_whitespace().map(String::from).collect()
 }
 
-fn print_fen(root: &Arc<Node>) {
-    println!("{}", root.board);
+fn print_fen(tree: &Arc<Tree>) {
+    println!("{}", tree.board);
 }
 
 fn static_eval(input: Vec<String>) {
     let fen = &input[1..].join(" ");
-    let board = Board::new(fen);
+    let board = match Board::from_fen(fen) {
+        Ok(b) => b,
+        Err(e) => {
+            println!("Invalid fen {}: {}", fen, e);
+            return;
+        }
+    };
     let eval = eval::evaluate(&board);
 
     println!("{}", eval.1);
 }
 
+fn perft(tree: &Arc<Tree>, input: Vec<String>, split: bool) {
+    let depth = match input.get(1).map(|d| d.parse::<u32>()) {
+        Some(Ok(d)) => d,
+        _ => {
+            println!("Usage: {} <depth>", input[0]);
+            return;
+        }
+    };
+
+    let start = Instant::now();
+    let nodes = if split {
+        let mut nodes = 0;
+        for (mov, count) in perft::divide(&tree.board, depth) {
+            println!("{}: {}", mov, count);
+            nodes += count;
+        }
+        println!();
+        nodes
+    } else {
+        perft::perft(&tree.board, depth)
+    };
+    let elapsed = start.elapsed().as_millis();
+
+    println!("Nodes searched: {}", nodes);
+    println!(
+        "Time: {} ms, {} nps",
+        elapsed,
+        nodes as u128 * 1000 / elapsed.max(1)
+    );
+}
+
+fn bench(options: &[UciOption], input: Vec<String>) {
+    let millis = match input.get(1).map(|m| m.parse::<u64>()) {
+        None => 1000,
+        Some(Ok(m)) => m,
+        Some(Err(_)) => {
+            println!("Usage: bench [milliseconds per position]");
+            return;
+        }
+    };
+
+    let results = bench::bench(options, millis);
+    let base = results[0].1.max(1) as f32;
+    for (threads, nps) in results {
+        println!(
+            "Threads {:>2}: {:>9} nps, {:.2}x",
+            threads,
+            nps,
+            nps as f32 / base
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     fn tokenize(string: &str) -> Vec<String> {
-        string
-            .split_ascii_whitespace()
-            .map(|x| String::from(x))
-            .collect()
+        string.split_ascii_whitespace().map(String::from).collect()
     }
 
     fn resolve_fen(cmd: &str) -> String {
-        let root = Arc::new(Node::new(Board::new(STARTPOS)));
+        let tree = Arc::new(Tree::new(Board::new(STARTPOS), Vec::new()));
         let command_vec = tokenize(cmd);
-        let root = uci_position(root, command_vec);
+        let tree = uci_position(tree, command_vec);
 
-        root.board.to_string()
+        tree.board.to_string()
+    }
+
+    #[test]
+    fn setoption_clamps_spins() {
+        let (mut options, _) = initialize();
+        let quiescence = |options: &[UciOption]| match options
+            .iter()
+            .find(|x| x.name == "MCTS_Quiescence")
+            .unwrap()
+            .value
+        {
+            UciValue::Spin { value, .. } => value,
+            _ => panic!("MCTS_Quiescence should be a spin"),
+        };
+        uci_setoption(
+            &mut options,
+            tokenize("setoption name MCTS_Quiescence value -1"),
+        );
+        assert_eq!(quiescence(&options), 0);
+        uci_setoption(
+            &mut options,
+            tokenize("setoption name MCTS_Quiescence value 99"),
+        );
+        assert_eq!(quiescence(&options), 32);
+        uci_setoption(
+            &mut options,
+            tokenize("setoption name MCTS_Quiescence value 6"),
+        );
+        assert_eq!(quiescence(&options), 6);
+    }
+
+    #[test]
+    fn position_reuses_searched_tree() {
+        let (mut options, tree) = initialize();
+        uci_setoption(&mut options, tokenize("setoption name Threads value 2"));
+        let mut controller = SearchController::new(2);
+        let mut tree = uci_position(tree, tokenize("position startpos"));
+
+        // Each search's most visited move becomes the next root along with
+        // its subtree, also while the last tree is still being freed
+        let mut moves = String::new();
+        for _ in 0..3 {
+            uci_go(&mut controller, &tree, &options, tokenize("go nodes 20000"));
+            while controller.is_searching() {
+                std::thread::yield_now();
+            }
+            let best = tree
+                .children(tree.root())
+                .max_by_key(|&c| tree.node(c).visits())
+                .unwrap();
+            let visits = tree.node(best).visits();
+            assert!(visits > 1);
+            moves.push_str(&format!(" {}", tree.node(best).mov()));
+            let position = format!("position startpos moves{}", moves);
+            tree = uci_position(tree, tokenize(&position));
+            assert_eq!(tree.node(tree.root()).visits(), visits);
+            tree = uci_position(tree, tokenize(&position));
+            assert_eq!(tree.node(tree.root()).visits(), visits);
+        }
+
+        controller.shutdown();
+    }
+
+    #[test]
+    fn replaced_trees_are_dropped() {
+        let (_, tree) = initialize();
+        let old = Arc::downgrade(&tree);
+        let tree = uci_position(
+            tree,
+            tokenize("position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1"),
+        );
+        let older = Arc::downgrade(&tree);
+        let _tree = uci_newgame(tree);
+
+        // Neither tree is kept, and both go once their threads are done
+        while old.upgrade().is_some() || older.upgrade().is_some() {
+            std::thread::yield_now();
+        }
     }
 
     #[test]
@@ -634,6 +813,29 @@ mod tests {
         assert_eq!(resolve_fen("position fen rnbqkb1r/ppp1pppp/3p1n2/4P3/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3 moves e5f6 d8d7 f6g7 e8d8 g7h8Q d7h3 g1h3"), "rnbk1b1Q/ppp1pp1p/3p4/8/8/7N/PPPP1PPP/RNBQKB1R b KQ - 0 6");
     }
 
+    #[test]
+    fn position_invalid_fen() {
+        // A bad fen leaves the current root in place
+        assert_eq!(
+            resolve_fen("position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0"),
+            STARTPOS
+        );
+        assert_eq!(
+            resolve_fen("position fen 8/8/8/8/8/8/8/8 w - - 0 1 moves e2e4"),
+            STARTPOS
+        );
+    }
+
+    #[test]
+    fn position_illegal_move() {
+        // An illegal move rejects the whole command
+        assert_eq!(
+            resolve_fen("position startpos moves e2e4 e7e5 e1e3"),
+            STARTPOS
+        );
+        assert_eq!(resolve_fen("position startpos moves e2e4 e7"), STARTPOS);
+    }
+
     #[test]
     fn position() {
         // Starting position
@@ -642,4 +844,52 @@ mod tests {
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
         );
     }
+
+    #[test]
+    fn go_command() {
+        let go = |cmd: &str| parse_go_command(tokenize(cmd));
+
+        assert_eq!(
+            go("go infinite").unwrap(),
+            UciGo {
+                infinite: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            go("go wtime 60000 btime -20 winc 1000 binc 1000 movestogo 12 movetime 1000").unwrap(),
+            UciGo {
+                wtime: Some(60000),
+                btime: Some(0),
+                winc: Some(1000),
+                binc: Some(1000),
+                movestogo: Some(12),
+                movetime: Some(1000),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            go("go depth 10 nodes 5000 mate 3").unwrap(),
+            UciGo {
+                depth: Some(10),
+                nodes: Some(5000),
+                mate: Some(3),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            go("go searchmoves e2e4 D2D4 ponder wtime 100").unwrap(),
+            UciGo {
+                searchmoves: vec!["e2e4".to_string(), "d2d4".to_string()],
+                ponder: true,
+                wtime: Some(100),
+                ..Default::default()
+            }
+        );
+        assert_eq!(go("go").unwrap(), UciGo::default());
+
+        assert!(go("go depth").is_err());
+        assert!(go("go nodes many").is_err());
+        assert!(go("go fast").is_err());
+    }
 }
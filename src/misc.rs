@@ -15,7 +15,7 @@ pub fn cp_to_eval(cp: i32) -> f32 {
     if cp > 0 {
         ((cp.pow(2) + 10_000) as f32) / ((cp.pow(2) + 20_000) as f32)
     } else if cp < 0 {
-        let inverse = cp * -1;
+        let inverse = -cp;
         let eval = ((inverse.pow(2) + 10_000) as f32) / ((inverse.pow(2) + 20_000) as f32);
         1.0 - eval
     } else {
@@ -23,18 +23,32 @@ pub fn cp_to_eval(cp: i32) -> f32 {
     }
 }
 
+// Moves the side to move has left to play.  Inverts the
+// y = (1.015)**(-x - 46.56) + 0.5 curve train/parse_pgn.py labels positions
+// with, where x is the number of plies until the game ends.  Level positions
+// saturate at u32::MAX.
 pub fn eval_to_movestogo(eval: f32) -> u32 {
-    // Need inverse of y = (1.015)**(-x - 46.56) + 0.5
     let flip_eval = if eval < 0.5 { 1.0 - eval } else { eval };
+    let plies = -(flip_eval - 0.5).ln() / 1.015_f32.ln() - 46.56;
 
-    50
+    (plies.max(0.0) / 2.0) as u32
+}
+
+// Linear map of UCI_Elo onto Skill.  The ends of the Elo range are rough
+// estimates of Skill 1 and Skill 100 strength.
+pub const MIN_ELO: i32 = 1000;
+pub const MAX_ELO: i32 = 2500;
+
+pub fn elo_to_skill(elo: i32) -> i32 {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+    1 + (elo - MIN_ELO) * 99 / (MAX_ELO - MIN_ELO)
 }
 
 //PRNG Algorithm
 //Credit:
 //https://nullprogram.com/blog/2017/09/21/
 pub fn spcg32(state: &u64) -> (u32, u64) {
-    let state = Wrapping(state.clone());
+    let state = Wrapping(*state);
     let m = Wrapping(0x9b60933458e17d7du64);
     let a = Wrapping(0xd737232eeccdf7edu64);
     let new_state = state * m + a;
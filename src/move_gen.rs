@@ -1,47 +1,462 @@
+use std::fmt;
 use std::num::Wrapping;
-use std::sync::{Arc, RwLockWriteGuard};
 
-use crate::board::{self, Board, PieceType};
+use crate::board::{Board, PieceType};
 use crate::magic;
-use crate::search::{self, Node};
-
-const a_file_bb: u64 = 0x0101010101010101;
-const b_file_bb: u64 = 0x0202020202020202;
-const c_file_bb: u64 = 0x0404040404040404;
-const d_file_bb: u64 = 0x0808080808080808;
-const e_file_bb: u64 = 0x1010101010101010;
-const f_file_bb: u64 = 0x2020202020202020;
-const g_file_bb: u64 = 0x4040404040404040;
-const h_file_bb: u64 = 0x8080808080808080;
-const rank_1_bb: u64 = 0x00000000000000ff;
-const rank_2_bb: u64 = 0x000000000000ff00;
-const rank_3_bb: u64 = 0x0000000000ff0000;
-const rank_4_bb: u64 = 0x00000000ff000000;
-const rank_5_bb: u64 = 0x000000ff00000000;
-const rank_6_bb: u64 = 0x0000ff0000000000;
-const rank_7_bb: u64 = 0x00ff000000000000;
-const rank_8_bb: u64 = 0xff00000000000000;
-
-pub fn bloom(leaf: &Arc<Node>, mut children: RwLockWriteGuard<Vec<Arc<Node>>>) {
-    let w_pieces = leaf.board.w_p_bb
-        | leaf.board.w_n_bb
-        | leaf.board.w_b_bb
-        | leaf.board.w_r_bb
-        | leaf.board.w_q_bb
-        | leaf.board.w_k_bb;
-    let b_pieces = leaf.board.b_p_bb
-        | leaf.board.b_n_bb
-        | leaf.board.b_b_bb
-        | leaf.board.b_r_bb
-        | leaf.board.b_q_bb
-        | leaf.board.b_k_bb;
-
-    children.extend(gen_pawn_moves(leaf, w_pieces, b_pieces));
-    children.extend(gen_knight_moves(leaf, w_pieces, b_pieces));
-    children.extend(gen_bishop_moves(leaf, w_pieces, b_pieces));
-    children.extend(gen_rook_moves(leaf, w_pieces, b_pieces));
-    children.extend(gen_queen_moves(leaf, w_pieces, b_pieces));
-    children.extend(gen_king_moves(leaf, w_pieces, b_pieces));
+use crate::zobrist;
+
+const A_FILE_BB: u64 = 0x0101010101010101;
+const H_FILE_BB: u64 = 0x8080808080808080;
+const RANK_2_BB: u64 = 0x000000000000ff00;
+const RANK_7_BB: u64 = 0x00ff000000000000;
+
+// Move flags
+pub const CAPTURE: u8 = 0x1;
+pub const DOUBLE_PUSH: u8 = 0x2;
+pub const EN_PASSENT: u8 = 0x4;
+pub const CASTLE: u8 = 0x8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<PieceType>,
+    pub flags: u8,
+}
+
+pub type MoveList = Vec<Move>;
+
+impl Move {
+    // from and to must each have exactly one bit set
+    fn new(parent: &Board, from: u64, to: u64, promotion: Option<&PieceType>) -> Move {
+        let enemy_pieces = if parent.is_w_move {
+            parent.b_p_bb | parent.b_n_bb | parent.b_b_bb | parent.b_r_bb | parent.b_q_bb
+        } else {
+            parent.w_p_bb | parent.w_n_bb | parent.w_b_bb | parent.w_r_bb | parent.w_q_bb
+        };
+        let is_pawn = (parent.w_p_bb | parent.b_p_bb) & from > 0;
+        let is_king = (parent.w_k_bb | parent.b_k_bb) & from > 0;
+        let from = from.trailing_zeros() as u8;
+        let to = to.trailing_zeros() as u8;
+
+        let mut flags = 0;
+        if enemy_pieces & (0x1 << to) > 0 {
+            flags |= CAPTURE;
+        }
+        if is_pawn && parent.en_passent == Some(0x1 << to) {
+            flags |= CAPTURE | EN_PASSENT;
+        }
+        if is_pawn && (from as i32 - to as i32).abs() == 16 {
+            flags |= DOUBLE_PUSH;
+        }
+        if is_king && (from as i32 - to as i32).abs() == 2 {
+            flags |= CASTLE;
+        }
+
+        Move {
+            from,
+            to,
+            promotion: promotion.copied(),
+            flags,
+        }
+    }
+
+    // Fits the move in 20 bits so search tree nodes can store it compactly.
+    // Promotions keep their colour, offset by one so 0 means none.
+    pub fn pack(&self) -> u32 {
+        let promotion = self.promotion.map_or(0, |pt| pt as u32 + 1);
+        u32::from(self.from)
+            | u32::from(self.to) << 6
+            | promotion << 12
+            | u32::from(self.flags) << 16
+    }
+
+    pub fn unpack(bits: u32) -> Move {
+        let promotion = match (bits >> 12) & 0xf {
+            0 => None,
+            2 => Some(PieceType::WN),
+            3 => Some(PieceType::WB),
+            4 => Some(PieceType::WR),
+            5 => Some(PieceType::WQ),
+            8 => Some(PieceType::BN),
+            9 => Some(PieceType::BB),
+            10 => Some(PieceType::BR),
+            11 => Some(PieceType::BQ),
+            p => panic!("Packed move has invalid promotion {}!", p),
+        };
+        Move {
+            from: (bits & 0x3f) as u8,
+            to: ((bits >> 6) & 0x3f) as u8,
+            promotion,
+            flags: ((bits >> 16) & 0xf) as u8,
+        }
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from_col = (b'a' + self.from % 8) as char;
+        let to_col = (b'a' + self.to % 8) as char;
+        write!(
+            f,
+            "{}{}{}{}",
+            from_col,
+            self.from / 8 + 1,
+            to_col,
+            self.to / 8 + 1
+        )?;
+        match self.promotion {
+            Some(PieceType::WN) | Some(PieceType::BN) => write!(f, "n"),
+            Some(PieceType::WB) | Some(PieceType::BB) => write!(f, "b"),
+            Some(PieceType::WR) | Some(PieceType::BR) => write!(f, "r"),
+            Some(PieceType::WQ) | Some(PieceType::BQ) => write!(f, "q"),
+            Some(_) => panic!("Not a valid promotion type!"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Every legal move of the position.  Moves are checked for leaving the king
+// in check on the bitboards alone, without building the boards they lead to.
+pub fn legal_moves(board: &Board) -> MoveList {
+    let mut moves = gen_moves(board, true);
+    moves.retain(|&mov| is_legal(board, mov));
+    moves
+}
+
+// Every legal move of the position along with the board it leads to
+pub fn legal_children(board: &Board) -> Vec<(Move, Board)> {
+    let w_pieces =
+        board.w_p_bb | board.w_n_bb | board.w_b_bb | board.w_r_bb | board.w_q_bb | board.w_k_bb;
+    let b_pieces =
+        board.b_p_bb | board.b_n_bb | board.b_b_bb | board.b_r_bb | board.b_q_bb | board.b_k_bb;
+
+    let mut children = Vec::new();
+    children.extend(gen_pawn_moves(board, w_pieces, b_pieces));
+    children.extend(gen_knight_moves(board, w_pieces, b_pieces));
+    children.extend(gen_bishop_moves(board, w_pieces, b_pieces));
+    children.extend(gen_rook_moves(board, w_pieces, b_pieces));
+    children.extend(gen_queen_moves(board, w_pieces, b_pieces));
+    children.extend(gen_king_moves(board, w_pieces, b_pieces));
+    children
+}
+
+// Captures and queen promotions of the side to move, for quiescence search.
+// They are only pseudo legal, playing one may leave the king in check.
+pub fn captures(board: &Board) -> MoveList {
+    gen_moves(board, false)
+}
+
+// Pseudo legal moves of the side to move.  Without quiet moves only captures
+// and queen promotions are generated.
+fn gen_moves(board: &Board, quiets: bool) -> MoveList {
+    let (allies, enemies, pawns, knights, bishops, rooks, queens, king) = if board.is_w_move {
+        (
+            board.w_p_bb | board.w_n_bb | board.w_b_bb | board.w_r_bb | board.w_q_bb | board.w_k_bb,
+            board.b_p_bb | board.b_n_bb | board.b_b_bb | board.b_r_bb | board.b_q_bb,
+            board.w_p_bb,
+            board.w_n_bb,
+            board.w_b_bb,
+            board.w_r_bb,
+            board.w_q_bb,
+            board.w_k_bb,
+        )
+    } else {
+        (
+            board.b_p_bb | board.b_n_bb | board.b_b_bb | board.b_r_bb | board.b_q_bb | board.b_k_bb,
+            board.w_p_bb | board.w_n_bb | board.w_b_bb | board.w_r_bb | board.w_q_bb,
+            board.b_p_bb,
+            board.b_n_bb,
+            board.b_b_bb,
+            board.b_r_bb,
+            board.b_q_bb,
+            board.b_k_bb,
+        )
+    };
+    let all_pieces = allies | enemies | board.w_k_bb | board.b_k_bb;
+    let (last_rank, start_rank, promotions) = if board.is_w_move {
+        (
+            0xff00000000000000,
+            RANK_2_BB,
+            [PieceType::WQ, PieceType::WR, PieceType::WB, PieceType::WN],
+        )
+    } else {
+        (
+            0x00000000000000ff,
+            RANK_7_BB,
+            [PieceType::BQ, PieceType::BR, PieceType::BB, PieceType::BN],
+        )
+    };
+    let promotions = if quiets {
+        &promotions[..]
+    } else {
+        &promotions[..1]
+    };
+    // The enemy king is never a target
+    let targets = if quiets {
+        !(allies | enemies | board.w_k_bb | board.b_k_bb) | enemies
+    } else {
+        enemies
+    };
+
+    let mut moves = Vec::new();
+    let mut push = |from: u64, mut targets: u64| {
+        while targets > 0 {
+            let to = targets & (!targets + 1);
+            if pawns & from > 0 && to & last_rank > 0 {
+                for promotion in promotions.iter() {
+                    moves.push(Move::new(board, from, to, Some(promotion)));
+                }
+            } else {
+                moves.push(Move::new(board, from, to, None));
+            }
+            targets &= !to;
+        }
+    };
+
+    let mut p_bb = pawns;
+    while p_bb > 0 {
+        let from = p_bb & (!p_bb + 1);
+        let (ahead, two_ahead) = if board.is_w_move {
+            (from << 8, from << 16)
+        } else {
+            (from >> 8, from >> 16)
+        };
+        let mut to = solo_pawn_attacks(
+            from,
+            enemies | board.en_passent.unwrap_or(0),
+            board.is_w_move,
+        );
+        if ahead & !all_pieces > 0 && (quiets || ahead & last_rank > 0) {
+            to |= ahead;
+            if quiets && from & start_rank > 0 && two_ahead & !all_pieces > 0 {
+                to |= two_ahead;
+            }
+        }
+        push(from, to);
+        p_bb &= !from;
+    }
+    let mut n_bb = knights;
+    while n_bb > 0 {
+        let from = n_bb & (!n_bb + 1);
+        push(from, solo_knight_moves(from, allies) & targets);
+        n_bb &= !from;
+    }
+    let mut d_bb = bishops | queens;
+    while d_bb > 0 {
+        let from = d_bb & (!d_bb + 1);
+        push(from, solo_bishop_moves(from, allies, all_pieces) & targets);
+        d_bb &= !from;
+    }
+    let mut r_bb = rooks | queens;
+    while r_bb > 0 {
+        let from = r_bb & (!r_bb + 1);
+        push(from, solo_rook_moves(from, allies, all_pieces) & targets);
+        r_bb &= !from;
+    }
+    push(king, solo_king_moves(king, allies) & targets);
+
+    // The king may not castle out of, through or into check
+    if quiets {
+        let (kingside, queenside, home_rank) = if board.is_w_move {
+            (board.is_w_castle, board.is_w_q_castle, 0)
+        } else {
+            (board.is_b_castle, board.is_b_q_castle, 56)
+        };
+        let safe = |bb: u64| !is_attacked(board, !board.is_w_move, bb << home_rank);
+        if kingside
+            && all_pieces & (0x60 << home_rank) == 0
+            && safe(0x10)
+            && safe(0x20)
+            && safe(0x40)
+        {
+            moves.push(Move::new(board, king, 0x40 << home_rank, None));
+        }
+        if queenside && all_pieces & (0xe << home_rank) == 0 && safe(0x10) && safe(0x8) && safe(0x4)
+        {
+            moves.push(Move::new(board, king, 0x4 << home_rank, None));
+        }
+    }
+
+    moves
+}
+
+// Whether playing the pseudo legal move leaves the mover's king out of check
+fn is_legal(board: &Board, mov: Move) -> bool {
+    let from = 0x1 << mov.from;
+    let to = 0x1 << mov.to;
+    let (king, pawns, knights, bishops, rooks, kings) = if board.is_w_move {
+        (
+            board.w_k_bb,
+            board.b_p_bb,
+            board.b_n_bb,
+            board.b_b_bb | board.b_q_bb,
+            board.b_r_bb | board.b_q_bb,
+            board.b_k_bb,
+        )
+    } else {
+        (
+            board.b_k_bb,
+            board.w_p_bb,
+            board.w_n_bb,
+            board.w_b_bb | board.w_q_bb,
+            board.w_r_bb | board.w_q_bb,
+            board.w_k_bb,
+        )
+    };
+    let all_pieces = board.w_p_bb
+        | board.w_n_bb
+        | board.w_b_bb
+        | board.w_r_bb
+        | board.w_q_bb
+        | board.w_k_bb
+        | board.b_p_bb
+        | board.b_n_bb
+        | board.b_b_bb
+        | board.b_r_bb
+        | board.b_q_bb
+        | board.b_k_bb;
+
+    // En passent takes a pawn behind the landing square
+    let captured = match (mov.flags & EN_PASSENT > 0, board.is_w_move) {
+        (true, true) => to >> 8,
+        (true, false) => to << 8,
+        (false, _) => to,
+    };
+    let all_pieces = (all_pieces & !from & !captured) | to;
+    let king = if king & from > 0 { to } else { king };
+
+    let attackers = (solo_knight_moves(king, 0) & knights)
+        | solo_pawn_attacks(king, pawns, board.is_w_move)
+        | (solo_bishop_moves(king, 0, all_pieces) & bishops)
+        | (solo_rook_moves(king, 0, all_pieces) & rooks)
+        | (solo_king_moves(king, 0) & kings);
+    attackers & !captured == 0
+}
+
+// Plays a move generated for this position without generating the others.
+// Gives the same board as the move's entry in legal_children.
+pub fn make_move(parent: &Board, mov: Move) -> Board {
+    let from_bb = 0x1 << mov.from;
+    let to_bb = 0x1 << mov.to;
+    let mut board = *parent;
+    let moved_pt = get_piecetype(parent, from_bb).expect("Move has no piece to play!");
+
+    // Remove the captured piece, en passent takes a pawn behind the landing square
+    let captured_bb = if mov.flags & EN_PASSENT == 0 {
+        to_bb
+    } else if parent.is_w_move {
+        to_bb >> 8
+    } else {
+        to_bb << 8
+    };
+    let captured_pt = get_piecetype(parent, captured_bb);
+    if let Some(pt) = captured_pt {
+        *piece_bb(&mut board, pt) &= !captured_bb;
+        board.hash ^= zobrist::piece(pt, captured_bb);
+    }
+
+    // Move the piece, promoting it when asked
+    let placed_pt = mov.promotion.unwrap_or(moved_pt);
+    *piece_bb(&mut board, moved_pt) &= !from_bb;
+    *piece_bb(&mut board, placed_pt) |= to_bb;
+    board.hash ^= zobrist::piece(moved_pt, from_bb) ^ zobrist::piece(placed_pt, to_bb);
+
+    // Castling also moves the rook
+    if mov.flags & CASTLE > 0 {
+        let (rook_pt, rook_from, rook_to) = match mov.to {
+            6 => (PieceType::WR, 0x80, 0x20),
+            2 => (PieceType::WR, 0x1, 0x8),
+            62 => (PieceType::BR, 0x8000000000000000, 0x2000000000000000),
+            _ => (PieceType::BR, 0x100000000000000, 0x800000000000000),
+        };
+        *piece_bb(&mut board, rook_pt) &= !rook_from;
+        *piece_bb(&mut board, rook_pt) |= rook_to;
+        board.hash ^= zobrist::piece(rook_pt, rook_from) ^ zobrist::piece(rook_pt, rook_to);
+    }
+
+    // Remove castling rights
+    match moved_pt {
+        PieceType::WK => {
+            board.is_w_castle = false;
+            board.is_w_q_castle = false;
+        }
+        PieceType::BK => {
+            board.is_b_castle = false;
+            board.is_b_q_castle = false;
+        }
+        _ => (),
+    }
+
+    // Update other board fields
+    let is_pawn = moved_pt == PieceType::WP || moved_pt == PieceType::BP;
+    if is_pawn || captured_pt.is_some() {
+        board.halfmove_clock = 0;
+    } else {
+        board.halfmove_clock += 1;
+    }
+    board.en_passent = None;
+    if mov.flags & DOUBLE_PUSH > 0 {
+        // Only enabled when an enemy pawn stands beside the landing square
+        let (enemy_pawns, skipped_bb) = if parent.is_w_move {
+            (board.b_p_bb, to_bb >> 8)
+        } else {
+            (board.w_p_bb, to_bb << 8)
+        };
+        let beside_bb = ((to_bb & !A_FILE_BB) >> 1) | ((to_bb & !H_FILE_BB) << 1);
+        if enemy_pawns & beside_bb > 0 {
+            board.en_passent = Some(skipped_bb);
+        }
+    }
+
+    child(parent, board, mov).1
+}
+
+fn piece_bb(board: &mut Board, pt: PieceType) -> &mut u64 {
+    match pt {
+        PieceType::WP => &mut board.w_p_bb,
+        PieceType::WN => &mut board.w_n_bb,
+        PieceType::WB => &mut board.w_b_bb,
+        PieceType::WR => &mut board.w_r_bb,
+        PieceType::WQ => &mut board.w_q_bb,
+        PieceType::WK => &mut board.w_k_bb,
+        PieceType::BP => &mut board.b_p_bb,
+        PieceType::BN => &mut board.b_n_bb,
+        PieceType::BB => &mut board.b_b_bb,
+        PieceType::BR => &mut board.b_r_bb,
+        PieceType::BQ => &mut board.b_q_bb,
+        PieceType::BK => &mut board.b_k_bb,
+    }
+}
+
+// Finishes a child board whose pieces have already been moved and hashed.
+// Swaps out the old side to move, castling and en passent keys for the new ones.
+fn child(parent: &Board, mut board: Board, mov: Move) -> (Move, Board) {
+    board.hash ^= zobrist::state(parent);
+
+    // A rook captured on its home square takes the castling right with it
+    if board.w_r_bb & 0x80 == 0 {
+        board.is_w_castle = false;
+    }
+    if board.w_r_bb & 0x1 == 0 {
+        board.is_w_q_castle = false;
+    }
+    if board.b_r_bb & 0x8000000000000000 == 0 {
+        board.is_b_castle = false;
+    }
+    if board.b_r_bb & 0x100000000000000 == 0 {
+        board.is_b_q_castle = false;
+    }
+
+    if board.is_w_move {
+        board.is_w_move = false;
+    } else {
+        board.is_w_move = true;
+        board.fullmove_clock += 1;
+    }
+    board.hash ^= zobrist::state(&board);
+    debug_assert_eq!(board.hash, zobrist::hash(&board));
+    (mov, board)
 }
 
 fn get_piecetype(board: &Board, bb: u64) -> Option<PieceType> {
@@ -74,72 +489,28 @@ fn get_piecetype(board: &Board, bb: u64) -> Option<PieceType> {
     }
 }
 
-fn notate(from: u64, to: u64, promotion: Option<&PieceType>) -> String {
-    let from_pos = u64::trailing_zeros(from);
-    let to_pos = u64::trailing_zeros(to);
-
-    let from_row = from_pos / 8 + 1;
-    let from_col = match from_pos % 8 {
-        0 => 'a',
-        1 => 'b',
-        2 => 'c',
-        3 => 'd',
-        4 => 'e',
-        5 => 'f',
-        6 => 'g',
-        7 => 'h',
-        _ => panic!("Invalid column."),
-    };
-    let to_row = to_pos / 8 + 1;
-    let to_col = match to_pos % 8 {
-        0 => 'a',
-        1 => 'b',
-        2 => 'c',
-        3 => 'd',
-        4 => 'e',
-        5 => 'f',
-        6 => 'g',
-        7 => 'h',
-        _ => panic!("Invalid column."),
-    };
-
-    match promotion {
-        Some(p) => {
-            let c = match p {
-                &PieceType::WN | &PieceType::BN => 'n',
-                &PieceType::WB | &PieceType::BB => 'b',
-                &PieceType::WR | &PieceType::BR => 'r',
-                &PieceType::WQ | &PieceType::BQ => 'q',
-                _ => panic!("Not a valid promotion type!"),
-            };
-            format!("{}{}{}{}{}", from_col, from_row, to_col, to_row, c)
-        }
-        None => format!("{}{}{}{}", from_col, from_row, to_col, to_row),
-    }
-}
-
-fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
+fn gen_pawn_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
     let all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         let mut p_bb = board.w_p_bb;
         while p_bb.count_ones() > 0 {
             // Gets bitboard with only lsb set
             let lsb_p_bb = p_bb & (!p_bb + 1);
 
             // Promotion possibilities
-            let promotions = if lsb_p_bb & rank_7_bb > 0 {
+            let promotions = if lsb_p_bb & RANK_7_BB > 0 {
                 vec![PieceType::WN, PieceType::WB, PieceType::WR, PieceType::WQ]
             } else {
                 vec![PieceType::WP]
             };
 
             let one_ahead_bb = lsb_p_bb << 8;
-            let capture_bbs = if lsb_p_bb & a_file_bb > 0 {
+            let capture_bbs = if lsb_p_bb & A_FILE_BB > 0 {
                 vec![lsb_p_bb << 9]
-            } else if lsb_p_bb & h_file_bb > 0 {
+            } else if lsb_p_bb & H_FILE_BB > 0 {
                 vec![lsb_p_bb << 7]
             } else {
                 vec![lsb_p_bb << 7, lsb_p_bb << 9]
@@ -148,44 +519,48 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
                 // Move ahead one square
                 if one_ahead_bb & all_pieces == 0 {
                     board.w_p_bb &= !lsb_p_bb;
-                    match promotion {
-                        &PieceType::WP => board.w_p_bb |= one_ahead_bb,
-                        &PieceType::WN => board.w_n_bb |= one_ahead_bb,
-                        &PieceType::WB => board.w_b_bb |= one_ahead_bb,
-                        &PieceType::WR => board.w_r_bb |= one_ahead_bb,
-                        &PieceType::WQ => board.w_q_bb |= one_ahead_bb,
+                    match *promotion {
+                        PieceType::WP => board.w_p_bb |= one_ahead_bb,
+                        PieceType::WN => board.w_n_bb |= one_ahead_bb,
+                        PieceType::WB => board.w_b_bb |= one_ahead_bb,
+                        PieceType::WR => board.w_r_bb |= one_ahead_bb,
+                        PieceType::WQ => board.w_q_bb |= one_ahead_bb,
                         _ => panic!("Invalid promotion type for WP"),
                     }
+                    board.hash ^= zobrist::piece(PieceType::WP, lsb_p_bb)
+                        ^ zobrist::piece(*promotion, one_ahead_bb);
 
                     board.halfmove_clock = 0;
                     board.en_passent = None;
 
                     //King cannot be in check
                     if !is_attacked(&board, false, board.w_k_bb) {
-                        let last_move = if lsb_p_bb & rank_7_bb > 0 {
-                            notate(lsb_p_bb, one_ahead_bb, Some(promotion))
+                        let mov = if lsb_p_bb & RANK_7_BB > 0 {
+                            Move::new(parent, lsb_p_bb, one_ahead_bb, Some(promotion))
                         } else {
-                            notate(lsb_p_bb, one_ahead_bb, None)
+                            Move::new(parent, lsb_p_bb, one_ahead_bb, None)
                         };
-                        children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                        children.push(child(parent, board, mov));
                     }
-                    board = leaf.board;
+                    board = *parent;
                 }
 
                 // Move ahead two squares
-                if lsb_p_bb & rank_2_bb > 0
+                if lsb_p_bb & RANK_2_BB > 0
                     && one_ahead_bb & all_pieces == 0
                     && (lsb_p_bb << 16) & all_pieces == 0
                 {
                     board.w_p_bb &= !lsb_p_bb;
                     board.w_p_bb |= lsb_p_bb << 16;
+                    board.hash ^= zobrist::piece(PieceType::WP, lsb_p_bb)
+                        ^ zobrist::piece(PieceType::WP, lsb_p_bb << 16);
                     board.halfmove_clock = 0;
                     board.en_passent = None;
 
                     // Check for en_passent enablement
-                    let enemy_ep_pawns = if lsb_p_bb & a_file_bb > 0 {
+                    let enemy_ep_pawns = if lsb_p_bb & A_FILE_BB > 0 {
                         vec![lsb_p_bb << 17]
-                    } else if lsb_p_bb & h_file_bb > 0 {
+                    } else if lsb_p_bb & H_FILE_BB > 0 {
                         vec![lsb_p_bb << 15]
                     } else {
                         vec![lsb_p_bb << 15, lsb_p_bb << 17]
@@ -198,26 +573,24 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                     //King cannot be in check
                     if !is_attacked(&board, false, board.w_k_bb) {
-                        let last_move = notate(lsb_p_bb, lsb_p_bb << 16, None);
-                        children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                        let mov = Move::new(parent, lsb_p_bb, lsb_p_bb << 16, None);
+                        children.push(child(parent, board, mov));
                     }
-                    board = leaf.board;
+                    board = *parent;
                 }
 
                 // Check for captures
                 for capture_bb in capture_bbs.iter() {
-                    let captured_piece = get_piecetype(&board, capture_bb & b_pieces);
-                    if captured_piece.is_some() {
+                    if let Some(captured_pt) = get_piecetype(&board, capture_bb & b_pieces) {
                         board.w_p_bb &= !lsb_p_bb;
-                        match promotion {
-                            &PieceType::WP => board.w_p_bb |= capture_bb,
-                            &PieceType::WN => board.w_n_bb |= capture_bb,
-                            &PieceType::WB => board.w_b_bb |= capture_bb,
-                            &PieceType::WR => board.w_r_bb |= capture_bb,
-                            &PieceType::WQ => board.w_q_bb |= capture_bb,
+                        match *promotion {
+                            PieceType::WP => board.w_p_bb |= capture_bb,
+                            PieceType::WN => board.w_n_bb |= capture_bb,
+                            PieceType::WB => board.w_b_bb |= capture_bb,
+                            PieceType::WR => board.w_r_bb |= capture_bb,
+                            PieceType::WQ => board.w_q_bb |= capture_bb,
                             _ => panic!("Invalid promotion type for WP"),
                         }
-                        let captured_pt = captured_piece.unwrap();
                         match captured_pt {
                             PieceType::BP => board.b_p_bb &= !capture_bb,
                             PieceType::BN => board.b_n_bb &= !capture_bb,
@@ -226,44 +599,47 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
                             PieceType::BQ => board.b_q_bb &= !capture_bb,
                             _ => panic!("Invalid capture PieceType for WP"),
                         }
+                        board.hash ^= zobrist::piece(PieceType::WP, lsb_p_bb)
+                            ^ zobrist::piece(*promotion, *capture_bb)
+                            ^ zobrist::piece(captured_pt, *capture_bb);
 
                         board.halfmove_clock = 0;
                         board.en_passent = None;
 
                         //King cannot be in check
                         if !is_attacked(&board, false, board.w_k_bb) {
-                            let last_move = if lsb_p_bb & rank_7_bb > 0 {
-                                notate(lsb_p_bb, *capture_bb, Some(promotion))
+                            let mov = if lsb_p_bb & RANK_7_BB > 0 {
+                                Move::new(parent, lsb_p_bb, *capture_bb, Some(promotion))
                             } else {
-                                notate(lsb_p_bb, *capture_bb, None)
+                                Move::new(parent, lsb_p_bb, *capture_bb, None)
                             };
-                            children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                            children.push(child(parent, board, mov));
                         }
-                        board = leaf.board;
+                        board = *parent;
                     }
                 }
 
                 // Check for en-passent
-                match board.en_passent {
-                    Some(ep_bb) => {
-                        for capture_bb in capture_bbs.iter() {
-                            if capture_bb & ep_bb > 0 {
-                                board.w_p_bb &= !lsb_p_bb;
-                                board.w_p_bb |= ep_bb;
-                                board.b_p_bb &= !(ep_bb >> 8);
-                                board.en_passent = None;
-                                board.halfmove_clock = 0;
-
-                                //King cannot be in check
-                                if !is_attacked(&board, false, board.w_k_bb) {
-                                    let last_move = notate(lsb_p_bb, ep_bb, None);
-                                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
-                                }
-                                board = leaf.board;
+                if let Some(ep_bb) = board.en_passent {
+                    for capture_bb in capture_bbs.iter() {
+                        if capture_bb & ep_bb > 0 {
+                            board.w_p_bb &= !lsb_p_bb;
+                            board.w_p_bb |= ep_bb;
+                            board.b_p_bb &= !(ep_bb >> 8);
+                            board.hash ^= zobrist::piece(PieceType::WP, lsb_p_bb)
+                                ^ zobrist::piece(PieceType::WP, ep_bb)
+                                ^ zobrist::piece(PieceType::BP, ep_bb >> 8);
+                            board.en_passent = None;
+                            board.halfmove_clock = 0;
+
+                            //King cannot be in check
+                            if !is_attacked(&board, false, board.w_k_bb) {
+                                let mov = Move::new(parent, lsb_p_bb, ep_bb, None);
+                                children.push(child(parent, board, mov));
                             }
+                            board = *parent;
                         }
                     }
-                    None => (),
                 }
             }
 
@@ -278,16 +654,16 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             let lsb_p_bb = p_bb & (!p_bb + 1);
 
             // Promotion possibilities
-            let promotions = if lsb_p_bb & rank_2_bb > 0 {
+            let promotions = if lsb_p_bb & RANK_2_BB > 0 {
                 vec![PieceType::BN, PieceType::BB, PieceType::BR, PieceType::BQ]
             } else {
                 vec![PieceType::BP]
             };
 
             let one_ahead_bb = lsb_p_bb >> 8;
-            let capture_bbs = if lsb_p_bb & a_file_bb > 0 {
+            let capture_bbs = if lsb_p_bb & A_FILE_BB > 0 {
                 vec![lsb_p_bb >> 7]
-            } else if lsb_p_bb & h_file_bb > 0 {
+            } else if lsb_p_bb & H_FILE_BB > 0 {
                 vec![lsb_p_bb >> 9]
             } else {
                 vec![lsb_p_bb >> 7, lsb_p_bb >> 9]
@@ -296,44 +672,48 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
                 // Move ahead one square
                 if one_ahead_bb & all_pieces == 0 {
                     board.b_p_bb &= !lsb_p_bb;
-                    match promotion {
-                        &PieceType::BP => board.b_p_bb |= one_ahead_bb,
-                        &PieceType::BN => board.b_n_bb |= one_ahead_bb,
-                        &PieceType::BB => board.b_b_bb |= one_ahead_bb,
-                        &PieceType::BR => board.b_r_bb |= one_ahead_bb,
-                        &PieceType::BQ => board.b_q_bb |= one_ahead_bb,
+                    match *promotion {
+                        PieceType::BP => board.b_p_bb |= one_ahead_bb,
+                        PieceType::BN => board.b_n_bb |= one_ahead_bb,
+                        PieceType::BB => board.b_b_bb |= one_ahead_bb,
+                        PieceType::BR => board.b_r_bb |= one_ahead_bb,
+                        PieceType::BQ => board.b_q_bb |= one_ahead_bb,
                         _ => panic!("Invalid promotion type for BP"),
                     }
+                    board.hash ^= zobrist::piece(PieceType::BP, lsb_p_bb)
+                        ^ zobrist::piece(*promotion, one_ahead_bb);
 
                     board.halfmove_clock = 0;
                     board.en_passent = None;
 
                     //King cannot be in check
                     if !is_attacked(&board, true, board.b_k_bb) {
-                        let last_move = if lsb_p_bb & rank_2_bb > 0 {
-                            notate(lsb_p_bb, one_ahead_bb, Some(promotion))
+                        let mov = if lsb_p_bb & RANK_2_BB > 0 {
+                            Move::new(parent, lsb_p_bb, one_ahead_bb, Some(promotion))
                         } else {
-                            notate(lsb_p_bb, one_ahead_bb, None)
+                            Move::new(parent, lsb_p_bb, one_ahead_bb, None)
                         };
-                        children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                        children.push(child(parent, board, mov));
                     }
-                    board = leaf.board;
+                    board = *parent;
                 }
 
                 // Move ahead two squares
-                if lsb_p_bb & rank_7_bb > 0
+                if lsb_p_bb & RANK_7_BB > 0
                     && one_ahead_bb & all_pieces == 0
                     && (lsb_p_bb >> 16) & all_pieces == 0
                 {
                     board.b_p_bb &= !lsb_p_bb;
                     board.b_p_bb |= lsb_p_bb >> 16;
+                    board.hash ^= zobrist::piece(PieceType::BP, lsb_p_bb)
+                        ^ zobrist::piece(PieceType::BP, lsb_p_bb >> 16);
                     board.halfmove_clock = 0;
                     board.en_passent = None;
 
                     // Check for en_passent enablement
-                    let enemy_ep_pawns = if lsb_p_bb & a_file_bb > 0 {
+                    let enemy_ep_pawns = if lsb_p_bb & A_FILE_BB > 0 {
                         vec![lsb_p_bb >> 15]
-                    } else if lsb_p_bb & h_file_bb > 0 {
+                    } else if lsb_p_bb & H_FILE_BB > 0 {
                         vec![lsb_p_bb >> 17]
                     } else {
                         vec![lsb_p_bb >> 15, lsb_p_bb >> 17]
@@ -346,26 +726,24 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                     //King cannot be in check
                     if !is_attacked(&board, true, board.b_k_bb) {
-                        let last_move = notate(lsb_p_bb, lsb_p_bb >> 16, None);
-                        children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                        let mov = Move::new(parent, lsb_p_bb, lsb_p_bb >> 16, None);
+                        children.push(child(parent, board, mov));
                     }
-                    board = leaf.board;
+                    board = *parent;
                 }
 
                 // Check for captures
                 for capture_bb in capture_bbs.iter() {
-                    let captured_piece = get_piecetype(&board, capture_bb & w_pieces);
-                    if captured_piece.is_some() {
+                    if let Some(captured_pt) = get_piecetype(&board, capture_bb & w_pieces) {
                         board.b_p_bb &= !lsb_p_bb;
-                        match promotion {
-                            &PieceType::BP => board.b_p_bb |= capture_bb,
-                            &PieceType::BN => board.b_n_bb |= capture_bb,
-                            &PieceType::BB => board.b_b_bb |= capture_bb,
-                            &PieceType::BR => board.b_r_bb |= capture_bb,
-                            &PieceType::BQ => board.b_q_bb |= capture_bb,
+                        match *promotion {
+                            PieceType::BP => board.b_p_bb |= capture_bb,
+                            PieceType::BN => board.b_n_bb |= capture_bb,
+                            PieceType::BB => board.b_b_bb |= capture_bb,
+                            PieceType::BR => board.b_r_bb |= capture_bb,
+                            PieceType::BQ => board.b_q_bb |= capture_bb,
                             _ => panic!("Invalid promotion type for BP"),
                         }
-                        let captured_pt = captured_piece.unwrap();
                         match captured_pt {
                             PieceType::WP => board.w_p_bb &= !capture_bb,
                             PieceType::WN => board.w_n_bb &= !capture_bb,
@@ -374,44 +752,47 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
                             PieceType::WQ => board.w_q_bb &= !capture_bb,
                             _ => panic!("Invalid capture PieceType for BP"),
                         }
+                        board.hash ^= zobrist::piece(PieceType::BP, lsb_p_bb)
+                            ^ zobrist::piece(*promotion, *capture_bb)
+                            ^ zobrist::piece(captured_pt, *capture_bb);
 
                         board.halfmove_clock = 0;
                         board.en_passent = None;
 
                         //King cannot be in check
                         if !is_attacked(&board, true, board.b_k_bb) {
-                            let last_move = if lsb_p_bb & rank_2_bb > 0 {
-                                notate(lsb_p_bb, *capture_bb, Some(promotion))
+                            let mov = if lsb_p_bb & RANK_2_BB > 0 {
+                                Move::new(parent, lsb_p_bb, *capture_bb, Some(promotion))
                             } else {
-                                notate(lsb_p_bb, *capture_bb, None)
+                                Move::new(parent, lsb_p_bb, *capture_bb, None)
                             };
-                            children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                            children.push(child(parent, board, mov));
                         }
-                        board = leaf.board;
+                        board = *parent;
                     }
                 }
 
                 // Check for en-passent
-                match board.en_passent {
-                    Some(ep_bb) => {
-                        for capture_bb in capture_bbs.iter() {
-                            if capture_bb & ep_bb > 0 {
-                                board.b_p_bb &= !lsb_p_bb;
-                                board.b_p_bb |= ep_bb;
-                                board.w_p_bb &= !(ep_bb << 8);
-                                board.en_passent = None;
-                                board.halfmove_clock = 0;
-
-                                //King cannot be in check
-                                if !is_attacked(&board, true, board.b_k_bb) {
-                                    let last_move = notate(lsb_p_bb, ep_bb, None);
-                                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
-                                }
-                                board = leaf.board;
+                if let Some(ep_bb) = board.en_passent {
+                    for capture_bb in capture_bbs.iter() {
+                        if capture_bb & ep_bb > 0 {
+                            board.b_p_bb &= !lsb_p_bb;
+                            board.b_p_bb |= ep_bb;
+                            board.w_p_bb &= !(ep_bb << 8);
+                            board.hash ^= zobrist::piece(PieceType::BP, lsb_p_bb)
+                                ^ zobrist::piece(PieceType::BP, ep_bb)
+                                ^ zobrist::piece(PieceType::WP, ep_bb << 8);
+                            board.en_passent = None;
+                            board.halfmove_clock = 0;
+
+                            //King cannot be in check
+                            if !is_attacked(&board, true, board.b_k_bb) {
+                                let mov = Move::new(parent, lsb_p_bb, ep_bb, None);
+                                children.push(child(parent, board, mov));
                             }
+                            board = *parent;
                         }
                     }
-                    None => (),
                 }
             }
 
@@ -423,12 +804,12 @@ fn gen_pawn_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
     children
 }
 
-fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
-    let all_pieces = w_pieces | b_pieces;
+fn gen_knight_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
+    let _all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         let mut n_bb = board.w_n_bb;
         while n_bb > 0 {
             // Gets bitboard with only lsb set
@@ -442,24 +823,23 @@ fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_n_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_n_moves);
+                    match pt {
                         PieceType::BP => board.b_p_bb &= !lsb_solo_n_moves,
                         PieceType::BN => board.b_n_bb &= !lsb_solo_n_moves,
                         PieceType::BB => board.b_b_bb &= !lsb_solo_n_moves,
                         PieceType::BR => board.b_r_bb &= !lsb_solo_n_moves,
                         PieceType::BQ => board.b_q_bb &= !lsb_solo_n_moves,
-                        _ => panic!(format!(
-                            "Internal error: white knight cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: white knight cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the knight
                 board.w_n_bb |= lsb_solo_n_moves;
                 board.w_n_bb &= !lsb_n_bb;
+                board.hash ^= zobrist::piece(PieceType::WN, lsb_n_bb)
+                    ^ zobrist::piece(PieceType::WN, lsb_solo_n_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -471,10 +851,10 @@ fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, false, board.w_k_bb) {
-                    let last_move = notate(lsb_n_bb, lsb_solo_n_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_n_bb, lsb_solo_n_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_n_moves &= !lsb_solo_n_moves;
@@ -498,24 +878,23 @@ fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_n_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_n_moves);
+                    match pt {
                         PieceType::WP => board.w_p_bb &= !lsb_solo_n_moves,
                         PieceType::WN => board.w_n_bb &= !lsb_solo_n_moves,
                         PieceType::WB => board.w_b_bb &= !lsb_solo_n_moves,
                         PieceType::WR => board.w_r_bb &= !lsb_solo_n_moves,
                         PieceType::WQ => board.w_q_bb &= !lsb_solo_n_moves,
-                        _ => panic!(format!(
-                            "Internal error: black knight cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: black knight cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the knight
                 board.b_n_bb |= lsb_solo_n_moves;
                 board.b_n_bb &= !lsb_n_bb;
+                board.hash ^= zobrist::piece(PieceType::BN, lsb_n_bb)
+                    ^ zobrist::piece(PieceType::BN, lsb_solo_n_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -527,10 +906,10 @@ fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, true, board.b_k_bb) {
-                    let last_move = notate(lsb_n_bb, lsb_solo_n_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_n_bb, lsb_solo_n_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_n_moves &= !lsb_solo_n_moves;
@@ -544,12 +923,12 @@ fn gen_knight_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
     children
 }
 
-fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
+fn gen_bishop_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
     let all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         let mut b_bb = board.w_b_bb;
         while b_bb > 0 {
             // Gets bitboard with only lsb set
@@ -563,24 +942,23 @@ fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_b_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_b_moves);
+                    match pt {
                         PieceType::BP => board.b_p_bb &= !lsb_solo_b_moves,
                         PieceType::BN => board.b_n_bb &= !lsb_solo_b_moves,
                         PieceType::BB => board.b_b_bb &= !lsb_solo_b_moves,
                         PieceType::BR => board.b_r_bb &= !lsb_solo_b_moves,
                         PieceType::BQ => board.b_q_bb &= !lsb_solo_b_moves,
-                        _ => panic!(format!(
-                            "Internal error: white bishop cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: white bishop cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the bishop
                 board.w_b_bb |= lsb_solo_b_moves;
                 board.w_b_bb &= !lsb_b_bb;
+                board.hash ^= zobrist::piece(PieceType::WB, lsb_b_bb)
+                    ^ zobrist::piece(PieceType::WB, lsb_solo_b_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -592,10 +970,10 @@ fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, false, board.w_k_bb) {
-                    let last_move = notate(lsb_b_bb, lsb_solo_b_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_b_bb, lsb_solo_b_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_b_moves &= !lsb_solo_b_moves;
@@ -619,24 +997,23 @@ fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_b_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_b_moves);
+                    match pt {
                         PieceType::WP => board.w_p_bb &= !lsb_solo_b_moves,
                         PieceType::WN => board.w_n_bb &= !lsb_solo_b_moves,
                         PieceType::WB => board.w_b_bb &= !lsb_solo_b_moves,
                         PieceType::WR => board.w_r_bb &= !lsb_solo_b_moves,
                         PieceType::WQ => board.w_q_bb &= !lsb_solo_b_moves,
-                        _ => panic!(format!(
-                            "Internal error: black bishop cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: black bishop cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the knight
                 board.b_b_bb |= lsb_solo_b_moves;
                 board.b_b_bb &= !lsb_b_bb;
+                board.hash ^= zobrist::piece(PieceType::BB, lsb_b_bb)
+                    ^ zobrist::piece(PieceType::BB, lsb_solo_b_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -648,10 +1025,10 @@ fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, true, board.b_k_bb) {
-                    let last_move = notate(lsb_b_bb, lsb_solo_b_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_b_bb, lsb_solo_b_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_b_moves &= !lsb_solo_b_moves;
@@ -665,12 +1042,12 @@ fn gen_bishop_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<N
     children
 }
 
-fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
+fn gen_rook_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
     let all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         let mut r_bb = board.w_r_bb;
         while r_bb > 0 {
             // Gets bitboard with only lsb set
@@ -684,21 +1061,23 @@ fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_r_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_r_moves);
+                    match pt {
                         PieceType::BP => board.b_p_bb &= !lsb_solo_r_moves,
                         PieceType::BN => board.b_n_bb &= !lsb_solo_r_moves,
                         PieceType::BB => board.b_b_bb &= !lsb_solo_r_moves,
                         PieceType::BR => board.b_r_bb &= !lsb_solo_r_moves,
                         PieceType::BQ => board.b_q_bb &= !lsb_solo_r_moves,
-                        _ => panic!(format!("Internal error: white rook cannot capture {}!", pt)),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: white rook cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the rook
                 board.w_r_bb |= lsb_solo_r_moves;
                 board.w_r_bb &= !lsb_r_bb;
+                board.hash ^= zobrist::piece(PieceType::WR, lsb_r_bb)
+                    ^ zobrist::piece(PieceType::WR, lsb_solo_r_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -715,10 +1094,10 @@ fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, false, board.w_k_bb) {
-                    let last_move = notate(lsb_r_bb, lsb_solo_r_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_r_bb, lsb_solo_r_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_r_moves &= !lsb_solo_r_moves;
@@ -742,21 +1121,23 @@ fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_r_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_r_moves);
+                    match pt {
                         PieceType::WP => board.w_p_bb &= !lsb_solo_r_moves,
                         PieceType::WN => board.w_n_bb &= !lsb_solo_r_moves,
                         PieceType::WB => board.w_b_bb &= !lsb_solo_r_moves,
                         PieceType::WR => board.w_r_bb &= !lsb_solo_r_moves,
                         PieceType::WQ => board.w_q_bb &= !lsb_solo_r_moves,
-                        _ => panic!(format!("Internal error: black rook cannot capture {}!", pt)),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: black rook cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the rook
                 board.b_r_bb |= lsb_solo_r_moves;
                 board.b_r_bb &= !lsb_r_bb;
+                board.hash ^= zobrist::piece(PieceType::BR, lsb_r_bb)
+                    ^ zobrist::piece(PieceType::BR, lsb_solo_r_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -773,10 +1154,10 @@ fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, true, board.b_k_bb) {
-                    let last_move = notate(lsb_r_bb, lsb_solo_r_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_r_bb, lsb_solo_r_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_r_moves &= !lsb_solo_r_moves;
@@ -790,12 +1171,12 @@ fn gen_rook_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
     children
 }
 
-fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
+fn gen_queen_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
     let all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         let mut q_bb = board.w_q_bb;
         while q_bb > 0 {
             // Gets bitboard with only lsb set
@@ -810,24 +1191,23 @@ fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<No
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_q_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_q_moves);
+                    match pt {
                         PieceType::BP => board.b_p_bb &= !lsb_solo_q_moves,
                         PieceType::BN => board.b_n_bb &= !lsb_solo_q_moves,
                         PieceType::BB => board.b_b_bb &= !lsb_solo_q_moves,
                         PieceType::BR => board.b_r_bb &= !lsb_solo_q_moves,
                         PieceType::BQ => board.b_q_bb &= !lsb_solo_q_moves,
-                        _ => panic!(format!(
-                            "Internal error: white queen cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: white queen cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the queen
                 board.w_q_bb |= lsb_solo_q_moves;
                 board.w_q_bb &= !lsb_q_bb;
+                board.hash ^= zobrist::piece(PieceType::WQ, lsb_q_bb)
+                    ^ zobrist::piece(PieceType::WQ, lsb_solo_q_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -839,10 +1219,10 @@ fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<No
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, false, board.w_k_bb) {
-                    let last_move = notate(lsb_q_bb, lsb_solo_q_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_q_bb, lsb_solo_q_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_q_moves &= !lsb_solo_q_moves;
@@ -867,24 +1247,23 @@ fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<No
 
                 // Strip all enemies away from landing square
                 let captured_pt = get_piecetype(&board, lsb_solo_q_moves);
-                match captured_pt {
-                    Some(ref pt) => match pt {
+                if let Some(ref pt) = captured_pt {
+                    board.hash ^= zobrist::piece(*pt, lsb_solo_q_moves);
+                    match pt {
                         PieceType::WP => board.w_p_bb &= !lsb_solo_q_moves,
                         PieceType::WN => board.w_n_bb &= !lsb_solo_q_moves,
                         PieceType::WB => board.w_b_bb &= !lsb_solo_q_moves,
                         PieceType::WR => board.w_r_bb &= !lsb_solo_q_moves,
                         PieceType::WQ => board.w_q_bb &= !lsb_solo_q_moves,
-                        _ => panic!(format!(
-                            "Internal error: black queen cannot capture {}!",
-                            pt
-                        )),
-                    },
-                    None => (),
+                        _ => panic!("Internal error: black queen cannot capture {}!", pt),
+                    }
                 }
 
                 // Move the queen
                 board.b_q_bb |= lsb_solo_q_moves;
                 board.b_q_bb &= !lsb_q_bb;
+                board.hash ^= zobrist::piece(PieceType::BQ, lsb_q_bb)
+                    ^ zobrist::piece(PieceType::BQ, lsb_solo_q_moves);
 
                 // Update other board fields
                 board.en_passent = None;
@@ -896,10 +1275,10 @@ fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<No
 
                 // If not in check then add to next moves
                 if !is_attacked(&board, true, board.b_k_bb) {
-                    let last_move = notate(lsb_q_bb, lsb_solo_q_moves, None);
-                    children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                    let mov = Move::new(parent, lsb_q_bb, lsb_solo_q_moves, None);
+                    children.push(child(parent, board, mov));
                 }
-                board = leaf.board;
+                board = *parent;
 
                 // Remove LSB on bitboard
                 solo_q_moves &= !lsb_solo_q_moves;
@@ -913,16 +1292,16 @@ fn gen_queen_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<No
     children
 }
 
-fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Node>> {
+fn gen_king_moves(parent: &Board, w_pieces: u64, b_pieces: u64) -> Vec<(Move, Board)> {
     let all_pieces = w_pieces | b_pieces;
     let mut children = Vec::new();
-    let mut board = leaf.board;
+    let mut board = *parent;
 
-    if leaf.board.is_w_move {
+    if parent.is_w_move {
         // Castling moves
         // Kingside
         if board.is_w_castle
-            && w_pieces & 0x60 == 0
+            && all_pieces & 0x60 == 0
             && !is_attacked(&board, false, board.w_k_bb)
             && !is_attacked(&board, false, 0x20)
             && !is_attacked(&board, false, 0x40)
@@ -930,8 +1309,11 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             // Move rook
             board.w_r_bb &= !0x80;
             board.w_r_bb |= 0x20;
+            board.hash ^= zobrist::piece(PieceType::WR, 0x80) ^ zobrist::piece(PieceType::WR, 0x20);
 
             // Move king
+            board.hash ^=
+                zobrist::piece(PieceType::WK, board.w_k_bb) ^ zobrist::piece(PieceType::WK, 0x40);
             board.w_k_bb = 0x40;
 
             // Other board changes
@@ -940,12 +1322,12 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             board.en_passent = None;
             board.halfmove_clock += 1;
 
-            children.push(Arc::new(Node::spawn(leaf, board, "e1g1".to_string())));
-            board = leaf.board;
+            children.push(child(parent, board, Move::new(parent, 0x10, 0x40, None)));
+            board = *parent;
         }
         // Queenside
         if board.is_w_q_castle
-            && w_pieces & 0xe == 0
+            && all_pieces & 0xe == 0
             && !is_attacked(&board, false, board.w_k_bb)
             && !is_attacked(&board, false, 0x4)
             && !is_attacked(&board, false, 0x8)
@@ -953,8 +1335,11 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             // Move rook
             board.w_r_bb &= !0x1;
             board.w_r_bb |= 0x8;
+            board.hash ^= zobrist::piece(PieceType::WR, 0x1) ^ zobrist::piece(PieceType::WR, 0x8);
 
             // Move king
+            board.hash ^=
+                zobrist::piece(PieceType::WK, board.w_k_bb) ^ zobrist::piece(PieceType::WK, 0x4);
             board.w_k_bb = 0x4;
 
             // Other board changes
@@ -963,8 +1348,8 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             board.en_passent = None;
             board.halfmove_clock += 1;
 
-            children.push(Arc::new(Node::spawn(leaf, board, "e1c1".to_string())));
-            board = leaf.board;
+            children.push(child(parent, board, Move::new(parent, 0x10, 0x4, None)));
+            board = *parent;
         }
 
         // Standard moves
@@ -978,21 +1363,23 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
             // Strip all enemies away from landing square
             let captured_pt = get_piecetype(&board, lsb_solo_k_moves);
-            match captured_pt {
-                Some(ref pt) => match pt {
+            if let Some(ref pt) = captured_pt {
+                board.hash ^= zobrist::piece(*pt, lsb_solo_k_moves);
+                match pt {
                     PieceType::BP => board.b_p_bb &= !lsb_solo_k_moves,
                     PieceType::BN => board.b_n_bb &= !lsb_solo_k_moves,
                     PieceType::BB => board.b_b_bb &= !lsb_solo_k_moves,
                     PieceType::BR => board.b_r_bb &= !lsb_solo_k_moves,
                     PieceType::BQ => board.b_q_bb &= !lsb_solo_k_moves,
-                    _ => panic!(format!("Internal error: white king cannot capture {}!", pt)),
-                },
-                None => (),
+                    _ => panic!("Internal error: white king cannot capture {}!", pt),
+                }
             }
 
             // Move the king
             board.w_k_bb |= lsb_solo_k_moves;
             board.w_k_bb &= !k_bb;
+            board.hash ^= zobrist::piece(PieceType::WK, k_bb)
+                ^ zobrist::piece(PieceType::WK, lsb_solo_k_moves);
 
             // Remove castling rights
             board.is_w_castle = false;
@@ -1008,10 +1395,10 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
             // If not in check then add to next moves
             if !is_attacked(&board, false, board.w_k_bb) {
-                let last_move = notate(k_bb, lsb_solo_k_moves, None);
-                children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                let mov = Move::new(parent, k_bb, lsb_solo_k_moves, None);
+                children.push(child(parent, board, mov));
             }
-            board = leaf.board;
+            board = *parent;
 
             // Remove LSB on bitboard
             solo_k_moves &= !lsb_solo_k_moves;
@@ -1021,7 +1408,7 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
         // Castling moves
         // Kingside
         if board.is_b_castle
-            && b_pieces & 0x6000000000000000 == 0
+            && all_pieces & 0x6000000000000000 == 0
             && !is_attacked(&board, true, board.b_k_bb)
             && !is_attacked(&board, true, 0x2000000000000000)
             && !is_attacked(&board, true, 0x4000000000000000)
@@ -1029,8 +1416,12 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             // Move rook
             board.b_r_bb &= !0x8000000000000000;
             board.b_r_bb |= 0x2000000000000000;
+            board.hash ^= zobrist::piece(PieceType::BR, 0x8000000000000000)
+                ^ zobrist::piece(PieceType::BR, 0x2000000000000000);
 
             // Move king
+            board.hash ^= zobrist::piece(PieceType::BK, board.b_k_bb)
+                ^ zobrist::piece(PieceType::BK, 0x4000000000000000);
             board.b_k_bb = 0x4000000000000000;
 
             // Other board changes
@@ -1039,12 +1430,16 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             board.en_passent = None;
             board.halfmove_clock += 1;
 
-            children.push(Arc::new(Node::spawn(leaf, board, "e8g8".to_string())));
-            board = leaf.board;
+            children.push(child(
+                parent,
+                board,
+                Move::new(parent, 0x1000000000000000, 0x4000000000000000, None),
+            ));
+            board = *parent;
         }
         // Queenside
         if board.is_b_q_castle
-            && b_pieces & 0xe00000000000000 == 0
+            && all_pieces & 0xe00000000000000 == 0
             && !is_attacked(&board, true, board.b_k_bb)
             && !is_attacked(&board, true, 0x400000000000000)
             && !is_attacked(&board, true, 0x800000000000000)
@@ -1052,8 +1447,12 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             // Move rook
             board.b_r_bb &= !0x100000000000000;
             board.b_r_bb |= 0x800000000000000;
+            board.hash ^= zobrist::piece(PieceType::BR, 0x100000000000000)
+                ^ zobrist::piece(PieceType::BR, 0x800000000000000);
 
             // Move king
+            board.hash ^= zobrist::piece(PieceType::BK, board.b_k_bb)
+                ^ zobrist::piece(PieceType::BK, 0x400000000000000);
             board.b_k_bb = 0x400000000000000;
 
             // Other board changes
@@ -1062,8 +1461,12 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
             board.en_passent = None;
             board.halfmove_clock += 1;
 
-            children.push(Arc::new(Node::spawn(leaf, board, "e8c8".to_string())));
-            board = leaf.board;
+            children.push(child(
+                parent,
+                board,
+                Move::new(parent, 0x1000000000000000, 0x400000000000000, None),
+            ));
+            board = *parent;
         }
 
         // Standard moves
@@ -1077,21 +1480,23 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
             // Strip all enemies away from landing square
             let captured_pt = get_piecetype(&board, lsb_solo_k_moves);
-            match captured_pt {
-                Some(ref pt) => match pt {
+            if let Some(ref pt) = captured_pt {
+                board.hash ^= zobrist::piece(*pt, lsb_solo_k_moves);
+                match pt {
                     PieceType::WP => board.w_p_bb &= !lsb_solo_k_moves,
                     PieceType::WN => board.w_n_bb &= !lsb_solo_k_moves,
                     PieceType::WB => board.w_b_bb &= !lsb_solo_k_moves,
                     PieceType::WR => board.w_r_bb &= !lsb_solo_k_moves,
                     PieceType::WQ => board.w_q_bb &= !lsb_solo_k_moves,
-                    _ => panic!(format!("Internal error: black king cannot capture {}!", pt)),
-                },
-                None => (),
+                    _ => panic!("Internal error: black king cannot capture {}!", pt),
+                }
             }
 
             // Move the king
             board.b_k_bb |= lsb_solo_k_moves;
             board.b_k_bb &= !k_bb;
+            board.hash ^= zobrist::piece(PieceType::BK, k_bb)
+                ^ zobrist::piece(PieceType::BK, lsb_solo_k_moves);
 
             // Remove castling rights
             board.is_b_castle = false;
@@ -1107,10 +1512,10 @@ fn gen_king_moves(leaf: &Arc<Node>, w_pieces: u64, b_pieces: u64) -> Vec<Arc<Nod
 
             // If not in check then add to next moves
             if !is_attacked(&board, true, board.b_k_bb) {
-                let last_move = notate(k_bb, lsb_solo_k_moves, None);
-                children.push(Arc::new(Node::spawn(leaf, board, last_move)));
+                let mov = Move::new(parent, k_bb, lsb_solo_k_moves, None);
+                children.push(child(parent, board, mov));
             }
-            board = leaf.board;
+            board = *parent;
 
             // Remove LSB on bitboard
             solo_k_moves &= !lsb_solo_k_moves;
@@ -1189,14 +1594,14 @@ pub fn solo_knight_moves(bb: u64, ally_pieces: u64) -> u64 {
 pub fn solo_bishop_moves(bb: u64, ally_pieces: u64, all_pieces: u64) -> u64 {
     let pos = bb.trailing_zeros() as usize;
     let occupied_coll = magic::bishop_collisions[pos] & all_pieces;
-    let magic_ind = Wrapping(magic::bishop_magic_numbers[pos]) * Wrapping(occupied_coll) >> 55;
+    let magic_ind = (Wrapping(magic::bishop_magic_numbers[pos]) * Wrapping(occupied_coll)) >> 55;
     magic::bishop_magic_move_sets[pos][magic_ind.0 as usize] & !ally_pieces
 }
 
 pub fn solo_rook_moves(bb: u64, ally_pieces: u64, all_pieces: u64) -> u64 {
     let pos = bb.trailing_zeros() as usize;
     let occupied_coll = magic::rook_collisions[pos] & all_pieces;
-    let magic_ind = Wrapping(magic::rook_magic_numbers[pos]) * Wrapping(occupied_coll) >> 52;
+    let magic_ind = (Wrapping(magic::rook_magic_numbers[pos]) * Wrapping(occupied_coll)) >> 52;
     magic::rook_magic_move_sets[pos][magic_ind.0 as usize] & !ally_pieces
 }
 
@@ -1218,7 +1623,6 @@ fn solo_pawn_attacks(bb: u64, enemy_pawns: u64, is_white: bool) -> u64 {
 mod tests {
     use super::*;
     use crate::board;
-    use crate::search;
 
     #[test]
     fn test_move_gen() {
@@ -1229,13 +1633,88 @@ mod tests {
         }
     }
 
+    #[test]
+    fn legal_move_flags() {
+        let count =
+            |moves: &MoveList, flag: u8| moves.iter().filter(|m| m.flags & flag > 0).count();
+
+        // Kiwipete
+        let board = board::Board::new(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        );
+        let moves = legal_moves(&board);
+        assert_eq!(moves.len(), 48);
+        assert_eq!(count(&moves, CAPTURE), 8);
+        assert_eq!(count(&moves, CASTLE), 2);
+        assert_eq!(count(&moves, EN_PASSENT), 0);
+        assert_eq!(count(&moves, DOUBLE_PUSH), 2);
+
+        let board = board::Board::new("4k3/8/8/2pP4/8/8/8/4K2R w K c6 0 1");
+        let moves = legal_moves(&board);
+        let ep = moves.iter().find(|m| m.to_string() == "d5c6").unwrap();
+        assert_eq!(ep.flags, CAPTURE | EN_PASSENT);
+        let castle = moves.iter().find(|m| m.to_string() == "e1g1").unwrap();
+        assert_eq!(castle.flags, CASTLE);
+
+        let board = board::Board::new("4k3/8/8/8/8/8/6p1/4K2R b K - 0 1");
+        let moves = legal_moves(&board);
+        assert_eq!(count(&moves, CAPTURE), 4);
+        let promotion = moves.iter().find(|m| m.to_string() == "g2h1q").unwrap();
+        assert_eq!(promotion.promotion, Some(PieceType::BQ));
+        assert_eq!(promotion.flags, CAPTURE);
+
+        // Children agree with their moves
+        for (mov, child) in legal_children(&board).iter() {
+            let mut expected = board;
+            expected.do_move(&mov.to_string());
+            assert_eq!(child.to_string(), expected.to_string());
+        }
+    }
+
     fn validate_scenario(tup: &(String, Vec<String>)) {
-        let node = Arc::new(search::Node::new(board::Board::new(&tup.0)));
-        let guard = node.children.try_write().unwrap();
-        bloom(&node, guard);
+        let parent = board::Board::new(&tup.0);
+        let children = legal_children(&parent);
+        let child_fens: Vec<String> = children.iter().map(|x| x.1.to_string()).collect();
+
+        // Replaying a stored move rebuilds the same board
+        for (mov, child) in children.iter() {
+            let mov = Move::unpack(mov.pack());
+            assert_eq!(make_move(&parent, mov), *child, "{} from {}", mov, tup.0);
+        }
 
-        let children = node.children.read().unwrap();
-        let child_fens: Vec<String> = children.iter().map(|x| x.board.to_string()).collect();
+        // Checking legality on bitboards agrees with building every child
+        let mut generated: Vec<u32> = legal_moves(&parent).iter().map(|m| m.pack()).collect();
+        let mut built: Vec<u32> = children.iter().map(|(mov, _)| mov.pack()).collect();
+        generated.sort_unstable();
+        built.sort_unstable();
+        assert_eq!(generated, built, "legal moves from {}", tup.0);
+
+        // Captures and queen promotions are the legal ones once checked
+        let mut captured: Vec<u32> = captures(&parent)
+            .into_iter()
+            .filter(|&mov| {
+                let child = make_move(&parent, mov);
+                let king = if parent.is_w_move {
+                    child.w_k_bb
+                } else {
+                    child.b_k_bb
+                };
+                !is_attacked(&child, !parent.is_w_move, king)
+            })
+            .map(|mov| mov.pack())
+            .collect();
+        let mut expected: Vec<u32> = children
+            .iter()
+            .filter(|(mov, _)| match mov.promotion {
+                Some(PieceType::WQ) | Some(PieceType::BQ) => true,
+                Some(_) => false,
+                None => mov.flags & CAPTURE > 0,
+            })
+            .map(|(mov, _)| mov.pack())
+            .collect();
+        captured.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(captured, expected, "captures from {}", tup.0);
 
         // Make sure we contain all the required moves
         for answer in tup.1.iter() {
@@ -1252,6 +1731,7 @@ mod tests {
         assert_eq!(children.len(), tup.1.len());
     }
 
+    #[allow(clippy::vec_init_then_push)]
     fn load_scenarios() -> Vec<(String, Vec<String>)> {
         let mut scenarios = Vec::new();
 
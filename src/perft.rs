@@ -0,0 +1,110 @@
+use crate::board::Board;
+use crate::move_gen::{self, Move};
+
+// Counts the leaf nodes of the legal move tree to the given depth
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if depth == 1 {
+        return move_gen::legal_moves(board).len() as u64;
+    }
+    move_gen::legal_children(board)
+        .iter()
+        .map(|(_, child)| perft(child, depth - 1))
+        .sum()
+}
+
+// Perft split up by the first move
+pub fn divide(board: &Board, depth: u32) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    move_gen::legal_children(board)
+        .iter()
+        .map(|(mov, child)| (*mov, perft(child, depth - 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference counts from https://www.chessprogramming.org/Perft_Results
+    fn check(fen: &str, counts: &[u64]) {
+        let board = Board::new(fen);
+        for (depth, count) in counts.iter().enumerate() {
+            assert_eq!(
+                perft(&board, depth as u32 + 1),
+                *count,
+                "{} depth {}",
+                fen,
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn startpos() {
+        check(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902, 197281],
+        );
+    }
+
+    #[test]
+    fn kiwipete() {
+        check(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn position_3() {
+        check(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    #[test]
+    fn position_4() {
+        check(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        // Mirrored
+        check(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9467],
+        );
+    }
+
+    #[test]
+    fn position_5() {
+        check(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn position_6() {
+        check(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890],
+        );
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let board =
+            Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let split = divide(&board, 2);
+        assert_eq!(split.len(), 48);
+        assert_eq!(split.iter().map(|(_, n)| n).sum::<u64>(), 2039);
+        let castle = split.iter().find(|(m, _)| m.to_string() == "e1g1").unwrap();
+        assert_eq!(castle.1, 43);
+    }
+}
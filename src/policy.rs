@@ -0,0 +1,131 @@
+use crate::board::{Board, PieceType};
+use crate::eval;
+use crate::misc;
+use crate::move_gen::{self, Move, CAPTURE, EN_PASSENT};
+
+// Logits the heuristic policy adds up for a move.  Captures are ordered by
+// most valuable victim, least valuable attacker, in pawns.
+const CAPTURE_LOGIT: f32 = 1.0;
+const MVV_LVA_LOGIT: f32 = 0.25;
+const CHECK_LOGIT: f32 = 1.0;
+const PROMOTION_LOGIT: f32 = 2.0;
+const UNDERPROMOTION_LOGIT: f32 = -1.0;
+// Per pawn the static eval gains for the side moving, capped either way
+const EVAL_GAIN_LOGIT: f32 = 0.5;
+const MAX_EVAL_GAIN_CP: i32 = 400;
+
+// Pawns, knights, bishops, rooks, queens and kings in pawns
+const PIECE_VALUES: [f32; 6] = [1.0, 3.0, 3.0, 5.0, 9.0, 0.0];
+
+// The prior probability of each of a position's moves, for PUCT to share
+// exploration out by.  evals holds the static eval of each child board.
+pub fn priors(parent: &Board, children: &[(Move, Board)], evals: &[f32]) -> Vec<f32> {
+    let (_, parent_eval) = eval::evaluate(parent);
+    let logits: Vec<f32> = children
+        .iter()
+        .zip(evals.iter())
+        .map(|((mov, child), &eval)| logit(parent, parent_eval, *mov, child, eval))
+        .collect();
+
+    // Softmax, shifted by the largest logit so nothing overflows
+    let max = logits.iter().cloned().fold(f32::MIN, f32::max);
+    let weights: Vec<f32> = logits.iter().map(|l| (l - max).exp()).collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+fn logit(parent: &Board, parent_eval: f32, mov: Move, child: &Board, eval: f32) -> f32 {
+    let mut logit = 0.0;
+
+    if mov.flags & CAPTURE > 0 {
+        let attacker = piece_value(parent, mov.from);
+        let victim = if mov.flags & EN_PASSENT > 0 {
+            PIECE_VALUES[0]
+        } else {
+            piece_value(parent, mov.to)
+        };
+        logit += CAPTURE_LOGIT + MVV_LVA_LOGIT * (victim - attacker / 10.0);
+    }
+
+    let king = if child.is_w_move {
+        child.w_k_bb
+    } else {
+        child.b_k_bb
+    };
+    if move_gen::is_attacked(child, !child.is_w_move, king) {
+        logit += CHECK_LOGIT;
+    }
+
+    match mov.promotion {
+        Some(PieceType::WQ) | Some(PieceType::BQ) => logit += PROMOTION_LOGIT,
+        Some(_) => logit += UNDERPROMOTION_LOGIT,
+        None => (),
+    }
+
+    let gain = misc::eval_to_cp(eval) - misc::eval_to_cp(parent_eval);
+    let gain = if parent.is_w_move { gain } else { -gain };
+    let gain = gain.clamp(-MAX_EVAL_GAIN_CP, MAX_EVAL_GAIN_CP);
+    logit + EVAL_GAIN_LOGIT * gain as f32 / 100.0
+}
+
+// Value of the piece standing on the square, none when it is empty
+fn piece_value(board: &Board, square: u8) -> f32 {
+    let bb = 1 << square;
+    [
+        (board.w_p_bb | board.b_p_bb),
+        (board.w_n_bb | board.b_n_bb),
+        (board.w_b_bb | board.b_b_bb),
+        (board.w_r_bb | board.b_r_bb),
+        (board.w_q_bb | board.b_q_bb),
+        (board.w_k_bb | board.b_k_bb),
+    ]
+    .iter()
+    .position(|pieces| pieces & bb > 0)
+    .map_or(0.0, |piece| PIECE_VALUES[piece])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each move's prior, by its UCI name
+    fn move_priors(fen: &str) -> Vec<(String, f32)> {
+        let board = Board::new(fen);
+        let children = move_gen::legal_children(&board);
+        let evals: Vec<f32> = children
+            .iter()
+            .map(|(_, child)| eval::evaluate(child).1)
+            .collect();
+        children
+            .iter()
+            .zip(priors(&board, &children, &evals))
+            .map(|((mov, _), prior)| (mov.to_string(), prior))
+            .collect()
+    }
+
+    fn prior(priors: &[(String, f32)], mov: &str) -> f32 {
+        priors.iter().find(|(m, _)| m == mov).unwrap().1
+    }
+
+    #[test]
+    fn priors_are_a_distribution() {
+        let priors = move_priors("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let total: f32 = priors.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+        assert!(priors.iter().all(|(_, p)| *p > 0.0));
+    }
+
+    #[test]
+    fn tactical_moves_are_preferred() {
+        // The pawn on d4 can take the queen or the knight, or push
+        let priors = move_priors("4k3/8/8/2q1n3/3P4/8/8/4K3 w - - 0 1");
+        assert!(prior(&priors, "d4c5") > prior(&priors, "d4e5"));
+        assert!(prior(&priors, "d4e5") > prior(&priors, "d4d5"));
+
+        // Queening beats underpromoting, and checks beat quiet moves
+        let priors = move_priors("8/1P6/8/8/8/k7/8/4K2R w - - 0 1");
+        assert!(prior(&priors, "b7b8q") > prior(&priors, "b7b8n"));
+        assert!(prior(&priors, "b7b8q") > prior(&priors, "h1h2"));
+        assert!(prior(&priors, "h1h3") > prior(&priors, "h1h2"));
+    }
+}
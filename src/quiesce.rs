@@ -0,0 +1,158 @@
+use crate::board::Board;
+use crate::eval;
+use crate::misc;
+use crate::move_gen::{self, Move, EN_PASSENT};
+use crate::search::Ending;
+
+// A capture is skipped when even winning the piece for free, plus this much,
+// cannot lift the side to move up to the score it already has
+const DELTA_MARGIN_CP: i32 = 200;
+// Promoting a pawn to a queen gains this much besides any capture
+const PROMOTION_CP: i32 = 800;
+
+// Pawns, knights, bishops, rooks, queens and kings in centipawns
+const PIECE_CP: [i32; 6] = [100, 300, 300, 500, 900, 0];
+
+// The static eval, with its ending, refined by searching captures up to depth
+// plies deep so a leaf in the middle of an exchange is scored once the dust
+// has settled.  Depth 0 is the static eval alone.
+pub fn evaluate(board: &Board, depth: u32) -> (Option<Ending>, f32) {
+    let (end, eval) = eval::evaluate(board);
+    if depth == 0 || end.is_some() {
+        return (end, eval);
+    }
+    let stand_pat = mover_cp(board, eval);
+    let cp = search(board, i32::MIN + 1, i32::MAX, depth, stand_pat);
+    // Quiet positions keep the static eval exactly
+    if cp == stand_pat {
+        return (end, eval);
+    }
+    let cp = if board.is_w_move { cp } else { -cp };
+    (end, misc::cp_to_eval(cp))
+}
+
+// Fail soft alpha beta over captures, scored for the side to move.  Standing
+// pat on the static eval assumes the side to move has a quiet move at least as
+// good, so it never has to capture.
+fn search(board: &Board, mut alpha: i32, beta: i32, depth: u32, stand_pat: i32) -> i32 {
+    if depth == 0 || stand_pat >= beta {
+        return stand_pat;
+    }
+    let mut best = stand_pat;
+    alpha = alpha.max(stand_pat);
+
+    let mut captures: Vec<(i32, Move)> = move_gen::captures(board)
+        .into_iter()
+        .map(|mov| (gain(board, mov), mov))
+        .collect();
+    // Most valuable victim first, least valuable attacker first among those
+    captures.sort_by_key(|&(gain, mov)| (-gain, piece_cp(board, 1 << mov.from)));
+
+    for (gain, mov) in captures {
+        if stand_pat + gain + DELTA_MARGIN_CP < alpha {
+            break;
+        }
+        let child = move_gen::make_move(board, mov);
+        let king = if board.is_w_move {
+            child.w_k_bb
+        } else {
+            child.b_k_bb
+        };
+        if move_gen::is_attacked(&child, !board.is_w_move, king) {
+            continue;
+        }
+
+        // Only draws end a line here, by material or the fifty move rule
+        let (end, eval) = eval::evaluate(&child);
+        let score = match end {
+            Some(_) => 0,
+            None => -search(&child, -beta, -alpha, depth - 1, mover_cp(&child, eval)),
+        };
+        if score > best {
+            best = score;
+            if score >= beta {
+                break;
+            }
+            alpha = alpha.max(score);
+        }
+    }
+    best
+}
+
+// Centipawns the move wins outright
+fn gain(board: &Board, mov: Move) -> i32 {
+    let victim = if mov.flags & EN_PASSENT > 0 {
+        PIECE_CP[0]
+    } else {
+        piece_cp(board, 1 << mov.to)
+    };
+    match mov.promotion {
+        Some(_) => victim + PROMOTION_CP,
+        None => victim,
+    }
+}
+
+// Value of the piece standing on the square, none when it is empty
+fn piece_cp(board: &Board, bb: u64) -> i32 {
+    [
+        (board.w_p_bb | board.b_p_bb),
+        (board.w_n_bb | board.b_n_bb),
+        (board.w_b_bb | board.b_b_bb),
+        (board.w_r_bb | board.b_r_bb),
+        (board.w_q_bb | board.b_q_bb),
+        (board.w_k_bb | board.b_k_bb),
+    ]
+    .iter()
+    .position(|pieces| pieces & bb > 0)
+    .map_or(0, |piece| PIECE_CP[piece])
+}
+
+// The eval in centipawns for the side to move
+fn mover_cp(board: &Board, eval: f32) -> i32 {
+    let cp = misc::eval_to_cp(eval);
+    if board.is_w_move {
+        cp
+    } else {
+        -cp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cp(fen: &str, depth: u32) -> i32 {
+        misc::eval_to_cp(evaluate(&Board::new(fen), depth).1)
+    }
+
+    #[test]
+    fn hanging_pieces_are_taken() {
+        // Black to move can take the queen on d4 for free
+        let fen = "4k3/p7/4n3/8/3Q4/8/7P/4K3 b - - 0 1";
+        assert!(cp(fen, 0) > 500);
+        assert!(cp(fen, 4) < 0);
+
+        // Depth 0 is the static eval
+        assert_eq!(
+            evaluate(&Board::new(fen), 0).1,
+            eval::evaluate(&Board::new(fen)).1
+        );
+    }
+
+    #[test]
+    fn defended_pieces_are_recaptured() {
+        // Qxd5 wins a pawn only to lose the queen to exd5
+        let fen = "4k3/8/4p3/3p4/8/8/3Q4/4K3 w - - 0 1";
+        let board = Board::new(fen);
+        let qxd5 = move_gen::legal_children(&board)
+            .into_iter()
+            .find(|(mov, _)| mov.to_string() == "d2d5")
+            .unwrap()
+            .1;
+        assert!(misc::eval_to_cp(evaluate(&qxd5, 0).1) > 500);
+        assert!(misc::eval_to_cp(evaluate(&qxd5, 4).1) < 0);
+
+        // Standing pat means having captures never hurts the side to move
+        assert!(cp(fen, 4) >= cp(fen, 0));
+    }
+}
@@ -2,34 +2,35 @@ use crate::board;
 use crate::eval;
 use crate::misc;
 use crate::move_gen;
-use crate::UciGo::{Depth, Infinite, Movetime, Nodes, Time};
+use crate::policy;
+use crate::quiesce;
+use crate::time_manager::TimeManager;
+use crate::tree::{Node, NodeId, Tree};
 use crate::{UciGo, UciOption, UciValue};
-use std::cmp::{self, Ordering, PartialOrd};
+use std::cmp::{self, Ordering};
 use std::convert::TryFrom;
-use std::ops::Deref;
-use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::sync::{Arc, Mutex, RwLockWriteGuard};
+use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const SEED_XOR: u64 = 0x77de55f9d2fe1e0d;
+// Odd multiplier spreading the hashes folded into a transposition key
+const WINDOW_MIX: u64 = 0x9e3779b97f4a7c15;
 const AVG_CHILD_COUNT: f32 = 50.0;
-const MAX_GAME_LENGTH: u32 = 60;
-const TIME_EXTENSION_MULT_MAX: f32 = 3.0;
-const BYTES_PER_NODE: u64 = 880;
-
-#[derive(Debug)]
-pub struct Node {
-    pub board: board::Board,
-    visits: RwLock<u32>,
-    depth: RwLock<u32>,
-    eval: RwLock<f32>,
-    ending: RwLock<Option<Ending>>,
-    pub children: RwLock<Vec<Arc<Node>>>,
-    parent: Weak<Node>,
-    last_move: Option<String>,
-    //proc_threads is number of threads searching in this node's children
-    //helps spread out mcts search to prevent thread clumping
-    proc_threads: RwLock<u32>,
-}
+// PUCT constant at the default MCTS_Explore
+const PUCT_BASE: f32 = 20.0;
+// Share of the tree pruned away each time it fills MCTS_Hash
+const PRUNE_SHARE: f32 = 0.25;
+// Exponent of the PowerMean backup, higher leans further toward the best child
+const POWER_MEAN_EXPONENT: f32 = 4.0;
+// Largest drop in win probability from the best move Skill 1 may accept
+const SKILL_MAX_EVAL_LOSS: f32 = 0.3;
+// Shift in the value of a draw per point of Contempt
+const CONTEMPT_SCALE: f32 = 0.001;
+// Distance from level, in centipawns, at which contempt stops shifting evals
+const CONTEMPT_FADE_CP: f32 = 50.0;
+// Largest play style bonus Dynamism can give, in centipawns
+const DYNAMISM_MAX_CP: f32 = 150.0;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Ending {
@@ -40,249 +41,160 @@ pub enum Ending {
     BlackWin(u32),
 }
 
-impl PartialOrd for Node {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if !self.board.is_w_move {
-            // Root board is white to move.  This child board is black to move.
-            // Evaluated for white's perspective.
-            if other.board.is_w_move {
-                return None;
-            }
-            match *self.ending.read().unwrap() {
-                Some(le) => match *other.ending.read().unwrap() {
-                    Some(re) => match le {
-                        Ending::Draw => match re {
-                            Ending::Draw => Some(Ordering::Equal),
-                            Ending::WhiteWin(rm) => Some(Ordering::Less),
-                            Ending::BlackWin(rm) => Some(Ordering::Greater),
-                        },
-                        Ending::WhiteWin(lm) => match re {
-                            Ending::Draw => Some(Ordering::Greater),
-                            Ending::WhiteWin(rm) => Some(rm.cmp(&lm)),
-                            Ending::BlackWin(rm) => Some(Ordering::Greater),
-                        },
-                        Ending::BlackWin(lm) => match re {
-                            Ending::Draw => Some(Ordering::Less),
-                            Ending::WhiteWin(rm) => Some(Ordering::Less),
-                            Ending::BlackWin(rm) => Some(lm.cmp(&rm)),
-                        },
-                    },
-                    None => match le {
-                        Ending::Draw => {
-                            if *other.eval.read().unwrap() < 0.5 {
-                                Some(Ordering::Greater)
-                            } else if *other.eval.read().unwrap() > 0.5 {
-                                Some(Ordering::Less)
-                            } else {
-                                Some(Ordering::Equal)
-                            }
-                        }
-                        Ending::WhiteWin(lm) => Some(Ordering::Greater),
-                        Ending::BlackWin(lm) => Some(Ordering::Less),
-                    },
-                },
-                None => match *other.ending.read().unwrap() {
-                    Some(re) => match re {
-                        Ending::Draw => {
-                            if *self.eval.read().unwrap() > 0.5 {
-                                Some(Ordering::Greater)
-                            } else if *self.eval.read().unwrap() < 0.5 {
-                                Some(Ordering::Less)
-                            } else {
-                                Some(Ordering::Equal)
-                            }
-                        }
-                        Ending::WhiteWin(rm) => Some(Ordering::Less),
-                        Ending::BlackWin(rm) => Some(Ordering::Greater),
-                    },
-                    None => match self
-                        .visits
-                        .read()
-                        .unwrap()
-                        .partial_cmp(&other.visits.read().unwrap())
-                        .unwrap()
-                    {
-                        Ordering::Less => Some(Ordering::Less),
-                        Ordering::Greater => Some(Ordering::Greater),
-                        Ordering::Equal => self
-                            .eval
-                            .read()
-                            .unwrap()
-                            .partial_cmp(&other.eval.read().unwrap()),
-                    },
-                },
-            }
-        } else {
-            // Root board is black to move.  This child board is white to move.
-            // Evaluated for black's perspective.
-            if !other.board.is_w_move {
-                return None;
-            }
-            match *self.ending.read().unwrap() {
-                Some(le) => match *other.ending.read().unwrap() {
-                    Some(re) => match le {
-                        Ending::Draw => match re {
-                            Ending::Draw => Some(Ordering::Equal),
-                            Ending::WhiteWin(rm) => Some(Ordering::Greater),
-                            Ending::BlackWin(rm) => Some(Ordering::Less),
-                        },
-                        Ending::WhiteWin(lm) => match re {
-                            Ending::Draw => Some(Ordering::Less),
-                            Ending::WhiteWin(rm) => Some(lm.cmp(&rm)),
-                            Ending::BlackWin(rm) => Some(Ordering::Less),
-                        },
-                        Ending::BlackWin(lm) => match re {
-                            Ending::Draw => Some(Ordering::Greater),
-                            Ending::WhiteWin(rm) => Some(Ordering::Greater),
-                            Ending::BlackWin(rm) => Some(rm.cmp(&lm)),
-                        },
-                    },
-                    None => match le {
-                        Ending::Draw => {
-                            if *other.eval.read().unwrap() > 0.5 {
-                                Some(Ordering::Greater)
-                            } else if *other.eval.read().unwrap() < 0.5 {
-                                Some(Ordering::Less)
-                            } else {
-                                Some(Ordering::Equal)
-                            }
-                        }
-                        Ending::WhiteWin(lm) => Some(Ordering::Less),
-                        Ending::BlackWin(lm) => Some(Ordering::Greater),
-                    },
-                },
-                None => match *other.ending.read().unwrap() {
-                    Some(re) => match re {
-                        Ending::Draw => {
-                            if *self.eval.read().unwrap() < 0.5 {
-                                Some(Ordering::Greater)
-                            } else if *self.eval.read().unwrap() > 0.5 {
-                                Some(Ordering::Less)
-                            } else {
-                                Some(Ordering::Equal)
-                            }
-                        }
-                        Ending::WhiteWin(rm) => Some(Ordering::Greater),
-                        Ending::BlackWin(rm) => Some(Ordering::Less),
-                    },
-                    None => match self
-                        .visits
-                        .read()
-                        .unwrap()
-                        .partial_cmp(&other.visits.read().unwrap())
-                        .unwrap()
-                    {
-                        Ordering::Less => Some(Ordering::Less),
-                        Ordering::Greater => Some(Ordering::Greater),
-                        Ordering::Equal => other
-                            .eval
-                            .read()
-                            .unwrap()
-                            .partial_cmp(&self.eval.read().unwrap()),
-                    },
-                },
-            }
+impl Ending {
+    // The low two bits tag the kind of ending, wins keep the distance to
+    // mate above them.  0 is no ending.
+    pub fn pack(ending: Option<Ending>) -> u32 {
+        match ending {
+            None => 0,
+            Some(Ending::Draw) => 1,
+            Some(Ending::WhiteWin(m)) => 2 | m << 2,
+            Some(Ending::BlackWin(m)) => 3 | m << 2,
         }
     }
-}
 
-impl PartialEq for Node {
-    fn eq(&self, other: &Self) -> bool {
-        match self.partial_cmp(other) {
-            Some(o) => match o {
-                Ordering::Equal => true,
-                _ => false,
-            },
-            None => false,
+    pub fn unpack(bits: u32) -> Option<Ending> {
+        match bits & 0x3 {
+            0 => None,
+            1 => Some(Ending::Draw),
+            2 => Some(Ending::WhiteWin(bits >> 2)),
+            _ => Some(Ending::BlackWin(bits >> 2)),
         }
     }
 }
 
-impl Node {
-    pub fn new(pos: board::Board) -> Node {
-        Node {
-            board: pos,
-            visits: RwLock::new(1),
-            depth: RwLock::new(0),
-            eval: RwLock::new(0.5),
-            ending: RwLock::new(None),
-            children: RwLock::new(Vec::new()),
-            parent: Weak::new(),
-            last_move: None,
-            proc_threads: RwLock::new(0),
-        }
-    }
+// How a node's eval is backed up from its children's, and so how siblings are
+// ranked to match.  Minimax takes the best child's eval and ranks by eval,
+// Mean averages the children's evals weighted by visits and PowerMean does the
+// same in the power mean of the mover's evals, which leans toward the best
+// child without trusting it alone.  Both of those rank by visits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backup {
+    Minimax,
+    Mean,
+    PowerMean,
+}
 
-    pub fn sortable_copy(&self) -> Node {
-        Node {
-            board: self.board.clone(),
-            visits: RwLock::new(*self.visits.read().unwrap()),
-            depth: RwLock::new(*self.depth.read().unwrap()),
-            eval: RwLock::new(*self.eval.read().unwrap()),
-            ending: RwLock::new(self.ending.read().unwrap().clone()),
-            children: RwLock::new(vec![]),
-            parent: Weak::new(),
-            last_move: None,
-            proc_threads: RwLock::new(0),
+impl Backup {
+    pub fn from_option(name: &str) -> Backup {
+        match name {
+            "Mean" => Backup::Mean,
+            "PowerMean" => Backup::PowerMean,
+            _ => Backup::Minimax,
         }
     }
 
-    fn sort_children(&self) {
-        // This method is required to sort the children nodes to prevent multithreads from propogating
-        // changes into the child nodes that would cause flip-flopping during the main thread's sorting
-        // operation, which panics.  This method ensures the sorting is done outside of mutable Nodes
-        let mut children_snapshot: Vec<_> = {
-            let children = self.children.read().unwrap();
-            children
+    // The node's eval from its children's, as (visits, eval) pairs with
+    // proven endings already counted as wins or losses
+    fn eval(self, children: &[(u32, f32)], is_w_move: bool) -> f32 {
+        let mover = |eval: f32| if is_w_move { eval } else { 1.0 - eval };
+        let total: f32 = children.iter().map(|(visits, _)| *visits as f32).sum();
+        let value = match self {
+            Backup::Minimax => children
                 .iter()
-                .map(|child| {
-                    (Arc::clone(child), child.sortable_copy()) // Snapshot fields
-                })
-                .collect()
+                .map(|(_, eval)| mover(*eval))
+                .fold(f32::MIN, f32::max),
+            Backup::Mean => {
+                children
+                    .iter()
+                    .map(|(visits, eval)| *visits as f32 * mover(*eval))
+                    .sum::<f32>()
+                    / total
+            }
+            Backup::PowerMean => (children
+                .iter()
+                .map(|(visits, eval)| *visits as f32 * mover(*eval).powf(POWER_MEAN_EXPONENT))
+                .sum::<f32>()
+                / total)
+                .powf(POWER_MEAN_EXPONENT.recip()),
         };
+        mover(value)
+    }
+}
 
-        // Step 2: Sort the snapshot
-        children_snapshot.sort_unstable_by(|(_, sortable_a), (_, sortable_b)| {
-            sortable_b.partial_cmp(&sortable_a).unwrap()
-        });
+// A copy of a node's statistics that other threads cannot change while
+// siblings are being sorted
+#[derive(Copy, Clone)]
+struct Stats {
+    visits: u32,
+    eval: f32,
+    ending: Option<Ending>,
+}
 
-        // Step 3: Reorder the original `children` based on the sorted snapshot
-        *self.children.write().unwrap() = children_snapshot
-            .into_iter()
-            .map(|(child, _)| child)
-            .collect();
+impl Stats {
+    fn of(tree: &Tree, id: NodeId) -> Stats {
+        let value = tree.value(id);
+        Stats {
+            visits: tree.node(id).visits(),
+            eval: value.eval(),
+            ending: value.ending(),
+        }
     }
 
-    pub fn spawn(leaf: &Arc<Node>, mut board: board::Board, last_move: String) -> Node {
-        if board.is_w_move {
-            board.is_w_move = false;
+    // The sort key of the node for the side choosing between it and its
+    // siblings: proven wins first, fastest mate first, and proven losses last,
+    // slowest mate first.  Between them draws and unresolved nodes go by visits
+    // and then by eval, whatever the backup operator.  Each node's key depends
+    // on that node alone, so the order stays total whatever mix of endings the
+    // siblings have.
+    fn key(&self, is_w_move: bool) -> ((u8, i64), u32, f32) {
+        let rank = match (self.ending, is_w_move) {
+            (Some(Ending::WhiteWin(m)), true) | (Some(Ending::BlackWin(m)), false) => {
+                (2, -i64::from(m))
+            }
+            (Some(Ending::WhiteWin(m)), false) | (Some(Ending::BlackWin(m)), true) => {
+                (0, i64::from(m))
+            }
+            (Some(Ending::Draw), _) | (None, _) => (1, 0),
+        };
+        let eval = if is_w_move {
+            self.eval
         } else {
-            board.is_w_move = true;
-            board.fullmove_clock += 1;
-        }
-        let (end, eval) = eval::evaluate(&board);
-        Node {
-            board: board,
-            visits: RwLock::new(1),
-            depth: RwLock::new(0),
-            eval: RwLock::new(eval),
-            ending: RwLock::new(end),
-            children: RwLock::new(Vec::new()),
-            parent: Arc::downgrade(leaf),
-            last_move: Some(last_move),
-            proc_threads: RwLock::new(0),
-        }
+            1.0 - self.eval
+        };
+        (rank, self.visits, eval)
+    }
+
+    fn compare(&self, other: &Stats, is_w_move: bool) -> Ordering {
+        let (rank, visits, eval) = self.key(is_w_move);
+        let (other_rank, other_visits, other_eval) = other.key(is_w_move);
+        rank.cmp(&other_rank)
+            .then(visits.cmp(&other_visits))
+            .then(eval.total_cmp(&other_eval))
     }
 }
 
+// The node's children from best to worst for the side to move at the node
+fn ranked_children(tree: &Tree, id: NodeId, is_w_move: bool) -> Vec<NodeId> {
+    let mut children: Vec<(NodeId, Stats)> = tree
+        .children(id)
+        .map(|child| (child, Stats::of(tree, child)))
+        .collect();
+    children.sort_unstable_by(|(_, a), (_, b)| b.compare(a, is_w_move));
+    children.into_iter().map(|(child, _)| child).collect()
+}
+
+// Positions before the last capture or pawn move can never repeat.  line
+// holds the hashes of the positions from the root down to the board's parent.
+fn is_repetition(tree: &Tree, line: &[u64], board: &board::Board) -> bool {
+    line.iter()
+        .rev()
+        .chain(tree.history().iter().rev())
+        .take(board.halfmove_clock as usize)
+        .any(|h| *h == board.hash)
+}
+
 pub fn search(
-    root: Arc<Node>,
+    tree: Arc<Tree>,
     options: Vec<UciOption>,
     searching: Arc<Mutex<bool>>,
-    go_parms: UciGo,
+    pondering: Arc<Mutex<bool>>,
+    mut go_parms: UciGo,
     main: bool,
 ) {
     let start_time = Instant::now();
+    // The clock only starts running for a ponder search once it is hit
+    let mut clock_start = Instant::now();
     let mut last_info = Instant::now();
     let mut rng_state: u64 = (SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -356,6 +268,42 @@ pub fn search(
         } => value,
         _ => panic!("MCTS_Hash UCI Option should be a UciValue::Spin option!"),
     };
+    let quiescence = match options
+        .iter()
+        .find(|&x| x.name == "MCTS_Quiescence")
+        .unwrap()
+        .value
+    {
+        UciValue::Spin {
+            value,
+            default: _,
+            min: _,
+            max: _,
+        } => value,
+        _ => panic!("MCTS_Quiescence UCI Option should be a UciValue::Spin option!"),
+    };
+    let transpositions = match options
+        .iter()
+        .find(|&x| x.name == "MCTS_Transpositions")
+        .unwrap()
+        .value
+    {
+        UciValue::Check { value, default: _ } => value,
+        _ => panic!("MCTS_Transpositions UCI Option should be a UciValue::Check option!"),
+    };
+    let backup = match options
+        .iter()
+        .find(|&x| x.name == "MCTS_Backup")
+        .unwrap()
+        .value
+    {
+        UciValue::Combo {
+            ref value,
+            default: _,
+            vars: _,
+        } => Backup::from_option(value),
+        _ => panic!("MCTS_Backup UCI Option should be a UciValue::Combo option!"),
+    };
     let skill = match options.iter().find(|&x| x.name == "Skill").unwrap().value {
         UciValue::Spin {
             value,
@@ -365,7 +313,31 @@ pub fn search(
         } => value,
         _ => panic!("Skill UCI Option should be a UciValue::Spin option!"),
     };
-    let _contempt = match options
+    let limit_strength = match options
+        .iter()
+        .find(|&x| x.name == "UCI_LimitStrength")
+        .unwrap()
+        .value
+    {
+        UciValue::Check { value, default: _ } => value,
+        _ => panic!("UCI_LimitStrength UCI Option should be a UciValue::Check option!"),
+    };
+    let elo = match options.iter().find(|&x| x.name == "UCI_Elo").unwrap().value {
+        UciValue::Spin {
+            value,
+            default: _,
+            min: _,
+            max: _,
+        } => value,
+        _ => panic!("UCI_Elo UCI Option should be a UciValue::Spin option!"),
+    };
+    // UCI_Elo overrides Skill when strength is limited
+    let skill = if limit_strength {
+        misc::elo_to_skill(elo)
+    } else {
+        skill
+    };
+    let contempt = match options
         .iter()
         .find(|&x| x.name == "Contempt")
         .unwrap()
@@ -379,7 +351,7 @@ pub fn search(
         } => value,
         _ => panic!("Contempt UCI Option should be a UciValue::Spin option!"),
     };
-    let _dynamism = match options
+    let dynamism = match options
         .iter()
         .find(|&x| x.name == "Dynamism")
         .unwrap()
@@ -394,28 +366,101 @@ pub fn search(
         _ => panic!("Dynamism UCI Option should be a UciValue::Spin option!"),
     };
 
-    while *searching.lock().unwrap() {
+    let root = tree.node(tree.root());
+    let draw_eval = draw_eval(contempt, tree.board.is_w_move);
+    let dynamism_cp = dynamism_cp(dynamism);
+
+    // Illegal searchmoves are dropped, leaving none means every move is searched
+    let legal_moves: Vec<String> = move_gen::legal_moves(&tree.board)
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+    go_parms.searchmoves.retain(|m| legal_moves.contains(m));
+
+    let new_time_manager = |go_parms: &UciGo| {
+        TimeManager::new(
+            go_parms,
+            tree.board.is_w_move,
+            root.eval(),
+            move_overhead,
+            move_speed,
+        )
+    };
+    let mut time_manager = new_time_manager(&go_parms);
+    let hash_bytes = usize::try_from(mcts_hash).unwrap() * 1048576;
+    tree.set_capacity(hash_bytes);
+
+    // The main thread runs a playout even when the search was stopped before
+    // it began, so there is always a move to report
+    let mut first_playout = main;
+    // The root children to choose from, gathered once the root has them.
+    // Nothing restructures the root during a search, so they stay put.
+    let mut searched_moves: Vec<NodeId> = Vec::new();
+    while first_playout || *searching.lock().unwrap() {
+        if go_parms.ponder && !*pondering.lock().unwrap() {
+            go_parms.ponder = false;
+            clock_start = Instant::now();
+            time_manager = new_time_manager(&go_parms);
+        }
         // MutexGuard is already dropped due to not being assigned a variable
         // navigate through the tree to identify leaf node
-        let leaf = match find_and_bloom_leaf_node(&root, mcts_explore) {
-            Ok(n) => n,
-            Err(_) => break,
-        };
-        // propogate values back up the tree
-        propogate_values(&leaf);
+        {
+            let _playout = tree.playout();
+            let leaf = find_and_bloom_leaf_node(
+                &tree,
+                mcts_explore,
+                draw_eval,
+                dynamism_cp,
+                quiescence.max(0) as u32,
+                &go_parms.searchmoves,
+                transpositions,
+            );
+            first_playout = false;
+            match leaf {
+                // propogate values back up the tree
+                Ok(path) => propogate_values(&tree, &path, draw_eval, backup),
+                // The main thread makes room below
+                Err(_) if tree.is_full() => (),
+                // bestmove must wait for ponderhit or stop even if the tree is solved
+                Err(_) if go_parms.ponder || go_parms.infinite => {
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                Err(_) => break,
+            }
+        }
 
         if main {
+            if tree.is_full() {
+                let bytes = (tree.bytes() as f32 * PRUNE_SHARE) as usize;
+                // Nothing left to prune, the search cannot grow any further
+                if prune(&tree, bytes) == 0 {
+                    *searching.lock().unwrap() = false;
+                }
+            }
             if last_info.elapsed() >= Duration::from_secs(2) {
-                print_info(&root, multi_pv, &start_time, &mut rng_state);
+                print_info(
+                    &tree,
+                    multi_pv,
+                    &start_time,
+                    &go_parms.searchmoves,
+                    &mut rng_state,
+                );
                 last_info = Instant::now();
             }
+            if searched_moves.is_empty() {
+                searched_moves = tree
+                    .children(tree.root())
+                    .filter(|&c| is_searched(&tree, c, &go_parms.searchmoves))
+                    .collect();
+            }
             if stop_searching(
-                &root,
-                &start_time,
+                &tree,
+                &searched_moves,
+                &clock_start,
                 &go_parms,
                 move_overhead,
-                move_speed,
-                mcts_hash,
+                time_manager.as_mut(),
             ) {
                 let mut s = searching.lock().unwrap();
                 *s = false;
@@ -424,96 +469,193 @@ pub fn search(
     }
 
     if main {
+        // Helpers stop with the main thread, even when it quits on a solved
+        // tree, so the pool lets go of the tree once bestmove is out
+        *searching.lock().unwrap() = false;
+        if let Some(moves) = go_parms.mate {
+            if !mate_found(&tree, moves) {
+                println!("info string no mate in {} found", moves);
+            }
+        }
         // print info
-        print_info(&root, multi_pv, &start_time, &mut rng_state);
+        print_info(
+            &tree,
+            multi_pv,
+            &start_time,
+            &go_parms.searchmoves,
+            &mut rng_state,
+        );
         // print bestmove
-        let best_node = get_bestmove(&root, skill, &mut rng_state).unwrap();
-        println!("bestmove {}", best_node.last_move.as_ref().unwrap());
+        let is_w_move = tree.board.is_w_move;
+        match get_bestmove(
+            &tree,
+            tree.root(),
+            is_w_move,
+            skill,
+            &go_parms.searchmoves,
+            &mut rng_state,
+        ) {
+            // Suggest the expected reply for the GUI to ponder on
+            Some(best) => match get_bestmove(&tree, best, !is_w_move, 100, &[], &mut 0) {
+                Some(reply) => println!(
+                    "bestmove {} ponder {}",
+                    tree.node(best).mov(),
+                    tree.node(reply).mov()
+                ),
+                None => println!("bestmove {}", tree.node(best).mov()),
+            },
+            // A root that was never bloomed still has its legal moves, and
+            // only a finished game reports the null move
+            None => match move_gen::legal_moves(&tree.board).first() {
+                Some(mov) => println!("bestmove {}", mov),
+                None => println!("bestmove 0000"),
+            },
+        }
     }
 }
 
-fn print_info(root: &Arc<Node>, multi_pv: i32, start_time: &Instant, rng_state: &mut u64) {
-    root.sort_children();
-
+fn print_info(
+    tree: &Tree,
+    multi_pv: i32,
+    start_time: &Instant,
+    searchmoves: &[String],
+    _rng_state: &mut u64,
+) {
     let time = start_time.elapsed();
-    let nodes = *root.visits.read().unwrap();
+    let nodes = tree.node(tree.root()).visits();
     let nps = (nodes as f32) / time.as_secs_f32();
+    let is_w_move = tree.board.is_w_move;
 
-    let children = root.children.read().unwrap();
-    for i in 0..cmp::min(multi_pv as usize, children.len()) {
-        let child = Arc::clone(&children[i]);
-        let pv = get_pv(&child);
-        let eval = match *child.ending.read().unwrap() {
+    let children = root_moves(tree, searchmoves);
+    for (i, &id) in children.iter().take(multi_pv as usize).enumerate() {
+        let child = tree.value(id);
+        let pv = get_pv(tree, id, !is_w_move);
+        let eval = match child.ending() {
             Some(e) => match e {
                 Ending::Draw => "cp 0".to_string(),
-                Ending::WhiteWin(m) => format!("mate {}", m),
-                Ending::BlackWin(m) => format!("mate -{}", m),
+                Ending::WhiteWin(m) => uci_mate(m + 1, is_w_move),
+                Ending::BlackWin(m) => uci_mate(m + 1, !is_w_move),
             },
-            None => format!("cp {}", misc::eval_to_cp(*child.eval.read().unwrap())),
+            None => format!("cp {}", misc::eval_to_cp(child.eval())),
         };
-        let depth = *child.depth.read().unwrap();
-        println!("info multipv {} depth {} seldepth {} time {} nodes {} pv_nodes {} nps {} score {} tbhits 0 pv {}", i + 1, depth, depth, time.as_millis(), nodes, child.visits.read().unwrap(), nps, eval, pv.trim());
+        let depth = child.depth();
+        println!("info multipv {} depth {} seldepth {} time {} nodes {} pv_nodes {} nps {} score {} tbhits 0 pv {}", i + 1, depth, depth, time.as_millis(), nodes, tree.node(id).visits(), nps, eval, pv.trim());
     }
 }
 
-fn get_pv(node: &Arc<Node>) -> String {
-    let mut pv = String::new();
-    let mut next_node = Arc::clone(node);
-    pv.push_str(node.last_move.as_ref().unwrap());
+// UCI mate scores count moves rather than plies and are negative when the
+// side to move at the root is the one getting mated
+fn uci_mate(plies: u32, root_wins: bool) -> String {
+    let moves = plies.div_ceil(2);
+    if root_wins {
+        format!("mate {}", moves)
+    } else {
+        format!("mate -{}", moves)
+    }
+}
 
-    loop {
-        match get_bestmove(&next_node, 100, &mut 0) {
-            Some(n) => {
-                pv.push_str(" ");
-                pv.push_str(n.last_move.as_ref().unwrap());
-                next_node = n;
-            }
-            None => break,
-        }
+// The line starting with the node's move.  is_w_move is the side to move at
+// the node.
+fn get_pv(tree: &Tree, id: NodeId, is_w_move: bool) -> String {
+    let mut pv = tree.node(id).mov().to_string();
+    let mut next_node = id;
+    let mut is_w_move = is_w_move;
+
+    while let Some(n) = get_bestmove(tree, next_node, is_w_move, 100, &[], &mut 0) {
+        pv.push(' ');
+        pv.push_str(&tree.node(n).mov().to_string());
+        next_node = n;
+        is_w_move = !is_w_move;
     }
 
     pv
 }
 
-fn get_bestmove(root: &Arc<Node>, skill: i32, rng_state: &mut u64) -> Option<Arc<Node>> {
-    root.sort_children();
-    let children = root.children.read().unwrap();
-    if children.len() > 0 {
-        Some(Arc::clone(&children[0]))
-    } else {
-        None
+fn get_bestmove(
+    tree: &Tree,
+    id: NodeId,
+    is_w_move: bool,
+    skill: i32,
+    searchmoves: &[String],
+    rng_state: &mut u64,
+) -> Option<NodeId> {
+    let children: Vec<NodeId> = ranked_children(tree, id, is_w_move)
+        .into_iter()
+        .filter(|&c| is_searched(tree, c, searchmoves))
+        .collect();
+    if children.is_empty() {
+        return None;
+    }
+    if skill >= 100 {
+        return Some(children[0]);
     }
 
-    /*
-    // Game is not over, select by node count
-    if skill < 100 {
-        println!("info Skill set to {}", skill);
+    // Below full skill every child's visit count is knocked down by a random
+    // fraction that grows as skill drops, so weaker settings pick moves the
+    // search liked less.  Children evaluated too far below the best move are
+    // never played so the engine does not simply blunder material.
+    let handicap = (100 - skill) as f32 / 100.0;
+    let max_loss = handicap * SKILL_MAX_EVAL_LOSS;
+    let best_eval = mover_eval(tree.value(children[0]), is_w_move);
 
-        let mut rng: u32;
-        for child in valid_children.iter().enumerate() {
-            let node = Arc::clone(child.1);
-            let (tmp_rng, tmp_rng_state) = misc::spcg32(rng_state);
-            rng = tmp_rng;
-            *rng_state = tmp_rng_state;
-            let percent_loss =
-                ((rng as f32) / (std::u32::MAX as f32)) * ((100 - skill) as f32 / 100 as f32) * 2.0;
-            let actual = *node.visits.read().unwrap() as f32;
-            children_sorted.push((child.0, actual - (actual * percent_loss)));
+    let mut best_index = 0;
+    let mut best_score = f32::MIN;
+    for (i, &child) in children.iter().enumerate() {
+        if best_eval - mover_eval(tree.value(child), is_w_move) > max_loss {
+            continue;
         }
-    } else {
-        for child in valid_children.iter().enumerate() {
-            let node = Arc::clone(child.1);
-            children_sorted.push((child.0, *node.visits.read().unwrap() as f32));
+        let (rng, new_state) = misc::spcg32(rng_state);
+        *rng_state = new_state;
+        let percent_loss = (rng as f32) / (u32::MAX as f32) * handicap * 2.0;
+        let visits = tree.node(child).visits() as f32;
+        let score = visits - visits * percent_loss;
+        if score > best_score {
+            best_score = score;
+            best_index = i;
         }
     }
-    */
+
+    Some(children[best_index])
 }
 
-fn best_move_adjudication(root: &Arc<Node>) -> Option<Arc<Node>> {
-    let children = root.children.read().unwrap();
+// The root children allowed by go searchmoves, all of them when it is empty,
+// best first.  Excluded children stay in the tree so a later search can reuse
+// them.
+fn root_moves(tree: &Tree, searchmoves: &[String]) -> Vec<NodeId> {
+    ranked_children(tree, tree.root(), tree.board.is_w_move)
+        .into_iter()
+        .filter(|&c| is_searched(tree, c, searchmoves))
+        .collect()
+}
+
+fn is_searched(tree: &Tree, child: NodeId, searchmoves: &[String]) -> bool {
+    searchmoves.is_empty() || {
+        let mov = tree.node(child).mov().to_string();
+        searchmoves.contains(&mov)
+    }
+}
+
+// Evaluation of a root child from the point of view of the side choosing it
+fn mover_eval(child: &Node, is_w_move: bool) -> f32 {
+    let eval = match child.ending() {
+        Some(Ending::WhiteWin(_)) => 1.0,
+        Some(Ending::BlackWin(_)) => 0.0,
+        Some(Ending::Draw) | None => child.eval(),
+    };
+    if is_w_move {
+        eval
+    } else {
+        1.0 - eval
+    }
+}
+
+#[allow(dead_code)]
+fn best_move_adjudication(tree: &Tree, draw_eval: f32) -> Option<NodeId> {
+    let root = tree.node(tree.root());
+    let children: Vec<NodeId> = tree.children(tree.root()).collect();
 
     // Check for game endings
-    match *root.ending.read().unwrap() {
+    match root.ending() {
         Some(e) => {
             let mut fast_w_win = u32::MAX;
             let mut slow_w_win = 0;
@@ -524,8 +666,8 @@ fn best_move_adjudication(root: &Arc<Node>) -> Option<Arc<Node>> {
             let mut fast_b_node = 0;
             let mut slow_b_node = 0;
             for child in children.iter().enumerate() {
-                match *child.1.ending.read().unwrap() {
-                    Some(c_e) => match c_e {
+                if let Some(c_e) = tree.value(*child.1).ending() {
+                    match c_e {
                         Ending::Draw => (),
                         Ending::WhiteWin(m) => {
                             if m < fast_w_win {
@@ -547,8 +689,7 @@ fn best_move_adjudication(root: &Arc<Node>) -> Option<Arc<Node>> {
                                 slow_b_node = child.0;
                             }
                         }
-                    },
-                    None => (),
+                    }
                 }
             }
             match e {
@@ -556,53 +697,52 @@ fn best_move_adjudication(root: &Arc<Node>) -> Option<Arc<Node>> {
                     // Get a random Draw child
                     let draw_child = children
                         .iter()
-                        .filter(|x| match *x.ending.read().unwrap() {
+                        .find(|&&x| match tree.value(x).ending() {
                             Some(e) => match e {
                                 Ending::Draw => true,
-                                Ending::WhiteWin(m) => false,
-                                Ending::BlackWin(m) => false,
+                                Ending::WhiteWin(_m) => false,
+                                Ending::BlackWin(_m) => false,
                             },
                             None => false,
                         })
-                        .nth(0)
                         .unwrap();
-                    Some(Arc::clone(draw_child))
+                    Some(*draw_child)
                 }
-                Ending::WhiteWin(m) => {
-                    if root.board.is_w_move {
-                        Some(Arc::clone(&children[fast_w_node]))
+                Ending::WhiteWin(_m) => {
+                    if tree.board.is_w_move {
+                        Some(children[fast_w_node])
                     } else {
-                        Some(Arc::clone(&children[slow_w_node]))
+                        Some(children[slow_w_node])
                     }
                 }
-                Ending::BlackWin(m) => {
-                    if root.board.is_w_move {
-                        Some(Arc::clone(&children[slow_b_node]))
+                Ending::BlackWin(_m) => {
+                    if tree.board.is_w_move {
+                        Some(children[slow_b_node])
                     } else {
-                        Some(Arc::clone(&children[fast_b_node]))
+                        Some(children[fast_b_node])
                     }
                 }
             }
         }
         None => {
             // Check to see if a draw is better than continuing
-            if (root.board.is_w_move && *root.eval.read().unwrap() < 0.5)
-                || (!root.board.is_w_move && *root.eval.read().unwrap() > 0.5)
+            if (tree.board.is_w_move && root.eval() < draw_eval)
+                || (!tree.board.is_w_move && root.eval() > draw_eval)
             {
                 // Get a random Draw child
-                let draw_children: Vec<&Arc<Node>> = children
+                let draw_children: Vec<&NodeId> = children
                     .iter()
-                    .filter(|x| match *x.ending.read().unwrap() {
+                    .filter(|&&x| match tree.value(x).ending() {
                         Some(e) => match e {
                             Ending::Draw => true,
-                            Ending::WhiteWin(m) => false,
-                            Ending::BlackWin(m) => false,
+                            Ending::WhiteWin(_m) => false,
+                            Ending::BlackWin(_m) => false,
                         },
                         None => false,
                     })
                     .collect();
-                if draw_children.len() > 0 {
-                    return Some(Arc::clone(draw_children[0]));
+                if !draw_children.is_empty() {
+                    return Some(*draw_children[0]);
                 }
             }
             None
@@ -610,326 +750,1199 @@ fn best_move_adjudication(root: &Arc<Node>) -> Option<Arc<Node>> {
     }
 }
 
-fn find_and_bloom_leaf_node(root: &Arc<Node>, mcts_explore: i32) -> Result<Arc<Node>, String> {
+// Walks down from the root to a leaf and blooms it.  Returns the path from
+// the root to the leaf.  With transpositions on, a leaf whose position was
+// already expanded elsewhere is linked to it and the walk carries on below.
+fn find_and_bloom_leaf_node(
+    tree: &Tree,
+    mcts_explore: i32,
+    draw_eval: f32,
+    dynamism_cp: f32,
+    quiescence: u32,
+    searchmoves: &[String],
+    transpositions: bool,
+) -> Result<Vec<NodeId>, String> {
+    let root = tree.root();
     'outer: loop {
-        if root.ending.read().unwrap().is_some() {
+        // An excluded move can decide the root's ending, so restricted
+        // searches run until the moves they were given are resolved
+        if searchmoves.is_empty() && tree.node(root).ending().is_some() {
             return Err("Game Over".to_string());
         }
-        let mut node = Arc::clone(root);
-        let mut returning = false;
-        let mut placeholder;
-        *node.proc_threads.write().unwrap() += 1;
+        let mut path = vec![root];
+        // Hashes of the positions along the path, for repetition detection
+        let mut line = Vec::new();
+        let mut board = tree.board;
+        let mut is_w_move = board.is_w_move;
+        tree.node(root).add_thread();
 
-        'inner: loop {
-            {
-                let children = node.children.read().unwrap();
-                if children.len() == 0 {
+        loop {
+            let id = *path.last().unwrap();
+            let children = tree.children(id);
+            if children.is_empty() {
+                let leaf = tree.resolve(id);
+                // Another thread is blooming the leaf
+                if !tree.claim(leaf) {
                     break;
                 }
+                let key = transposition_key(tree, &line, &board);
+                if transpositions && leaf == id {
+                    if let Some(shared) = transposition(tree, &path, key) {
+                        tree.link(id, shared);
+                        continue;
+                    }
+                }
 
-                let valid_children: Vec<&Arc<Node>> = children
-                    .iter()
-                    .filter(|x| x.ending.read().unwrap().is_none())
-                    .collect();
-                if valid_children.len() == 0 {
-                    decr_proc_threads(&node);
-                    continue 'outer;
+                line.push(board.hash);
+                if bloom(
+                    tree,
+                    leaf,
+                    &board,
+                    &line,
+                    draw_eval,
+                    dynamism_cp,
+                    quiescence,
+                ) {
+                    if transpositions {
+                        tree.add_transposition(key, leaf);
+                    }
+                    return Ok(path);
                 }
+                decr_proc_threads(tree, &path);
+                return Err("Tree full".to_string());
+            }
 
-                let mut children_sorted: Vec<(usize, f32)> = Vec::new();
-                for child in valid_children.iter().enumerate() {
-                    children_sorted.push((
-                        child.0,
-                        mcts_score(
-                            *child.1,
-                            mcts_explore,
-                            *node.visits.read().unwrap(),
-                            node.board.is_w_move,
-                        ),
-                    ));
+            let at_root = id == root;
+            let searchmoves = if at_root { searchmoves } else { &[] };
+            let best = match select_child(tree, id, mcts_explore, is_w_move, searchmoves) {
+                Some(c) => c,
+                None => {
+                    decr_proc_threads(tree, &path);
+                    if at_root {
+                        return Err("Search moves resolved".to_string());
+                    }
+                    continue 'outer;
                 }
+            };
+
+            line.push(board.hash);
+            board = tree.child_board(&board, best);
+            is_w_move = !is_w_move;
+            path.push(best);
+            tree.node(best).add_thread();
+        }
 
-                children_sorted.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        // Start back at beginning
+        decr_proc_threads(tree, &path);
+    }
+}
 
-                placeholder = Arc::clone(valid_children[children_sorted[0].0]);
-            }
+// The unresolved child with the best PUCT score, among searchmoves if any
+fn select_child(
+    tree: &Tree,
+    id: NodeId,
+    mcts_explore: i32,
+    is_w_move: bool,
+    searchmoves: &[String],
+) -> Option<NodeId> {
+    let parent_visits = tree.position_visits(id);
+    tree.children(id)
+        .filter(|&c| tree.value(c).ending().is_none())
+        .filter(|&c| is_searched(tree, c, searchmoves))
+        .map(|c| {
+            let score = mcts_score(tree, c, mcts_explore, parent_visits, is_w_move);
+            (c, score)
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(c, _)| c)
+}
 
-            node = Arc::clone(&placeholder);
-            *node.proc_threads.write().unwrap() += 1;
-        }
+// An expanded node elsewhere in the tree with the leaf's position.  One of
+// the leaf's own ancestors would make a cycle, which only a hash collision
+// can bring about as repetitions are draws.
+fn transposition(tree: &Tree, path: &[NodeId], key: u64) -> Option<NodeId> {
+    let leaf = *path.last().unwrap();
+    tree.transposition(key)
+        .filter(|&shared| shared != leaf && !path.iter().any(|&id| tree.resolve(id) == shared))
+}
+
+// Key under which a position is shared between move orders.  Repetition and
+// fifty move draws below a node depend on the positions since the last capture
+// or pawn move before it, so those and the halfmove clock go in with the
+// position's own hash.  line holds the hashes of the positions from the root
+// down to the board's parent.
+fn transposition_key(tree: &Tree, line: &[u64], board: &board::Board) -> u64 {
+    line.iter()
+        .rev()
+        .chain(tree.history().iter().rev())
+        .take(board.halfmove_clock as usize)
+        .fold(
+            board.hash ^ u64::from(board.halfmove_clock).wrapping_mul(WINDOW_MIX),
+            |key, h| key.wrapping_add(h.wrapping_mul(WINDOW_MIX)),
+        )
+}
 
-        match node.children.try_write() {
-            Ok(g) => {
-                move_gen::bloom(&node, g);
-                *node.depth.write().unwrap() = 1;
-                returning = true;
+// Gives a claimed leaf a child for every legal move, scored by the eval after
+// quiescence plies of captures, repetitions, contempt and play style, with its
+// prior from the policy.  A leaf without moves is checkmate or stalemate.
+// Fails when the tree has no room for the children.
+fn bloom(
+    tree: &Tree,
+    leaf: NodeId,
+    board: &board::Board,
+    line: &[u64],
+    draw_eval: f32,
+    dynamism_cp: f32,
+    quiescence: u32,
+) -> bool {
+    let engine_is_white = tree.board.is_w_move;
+    let moves = move_gen::legal_children(board);
+    let statics: Vec<_> = moves
+        .iter()
+        .map(|(_, child)| quiesce::evaluate(child, quiescence))
+        .collect();
+    let evals: Vec<f32> = statics.iter().map(|(_, eval)| *eval).collect();
+    let priors = policy::priors(board, &moves, &evals);
+    let children: Vec<_> = moves
+        .iter()
+        .zip(statics)
+        .zip(priors)
+        .map(|(((mov, child), (mut end, mut eval)), prior)| {
+            if end.is_none() && is_repetition(tree, line, child) {
+                end = Some(Ending::Draw);
             }
-            Err(_) => (),
-        }
+            match end {
+                Some(Ending::Draw) => eval = draw_eval,
+                None => {
+                    eval = score_style(eval, child, dynamism_cp, engine_is_white);
+                    eval = score_contempt(eval, draw_eval);
+                }
+                Some(_) => (),
+            }
+            (*mov, end, eval, prior)
+        })
+        .collect();
 
-        if returning {
-            return Ok(node);
+    let node = tree.node(leaf);
+    if children.is_empty() {
+        if board.is_w_move && move_gen::is_attacked(board, false, board.w_k_bb) {
+            node.set_ending(Some(Ending::BlackWin(0)));
+        } else if !board.is_w_move && move_gen::is_attacked(board, true, board.b_k_bb) {
+            node.set_ending(Some(Ending::WhiteWin(0)));
+        } else {
+            node.set_ending(Some(Ending::Draw));
+            node.set_eval(draw_eval);
         }
+    }
+    if !tree.expand(leaf, &children) {
+        return false;
+    }
+    node.set_depth(1);
+    true
+}
 
-        // Failed to lock leaf node, start back at beginning
-        decr_proc_threads(&node);
+// Value of a draw from white's perspective.  Positive contempt makes a draw
+// worth less than an equal position to the side the engine plays for, so it
+// plays on; negative contempt makes it seek draws.
+fn draw_eval(contempt: i32, engine_is_white: bool) -> f32 {
+    let shift = contempt as f32 * CONTEMPT_SCALE;
+    if engine_is_white {
+        0.5 - shift
+    } else {
+        0.5 + shift
     }
 }
 
-fn mcts_score(node: &Arc<Node>, mcts_explore: i32, parent_visits: u32, is_w_move: bool) -> f32 {
-    let eval = *node.eval.read().unwrap();
-    let visits = *node.visits.read().unwrap();
-    let threads = *node.proc_threads.read().unwrap();
-    let explore = ((parent_visits as f32).ln()
-        / ((visits as f32) + AVG_CHILD_COUNT * (threads as f32)))
-        .sqrt();
+// Near level positions are nearly draws, so they take the draw's contempt
+// shift, in full when dead level and fading out at CONTEMPT_FADE_CP
+fn score_contempt(eval: f32, draw_eval: f32) -> f32 {
+    let level = 1.0 - misc::eval_to_cp(eval).abs() as f32 / CONTEMPT_FADE_CP;
+    eval + (draw_eval - 0.5) * level.max(0.0)
+}
+
+// Centipawns given to the engine side in the sharpest possible position.
+// Dynamism 50 is neutral, 100 adds the full DYNAMISM_MAX_CP and 1 takes
+// nearly all of it away, steering the search toward quiet positions.
+fn dynamism_cp(dynamism: i32) -> f32 {
+    (dynamism - 50) as f32 / 50.0 * DYNAMISM_MAX_CP
+}
+
+// Newly bloomed children that are still in play get the play style bonus
+fn score_style(eval: f32, board: &board::Board, dynamism_cp: f32, engine_is_white: bool) -> f32 {
+    if dynamism_cp == 0.0 {
+        return eval;
+    }
+    let bonus = (dynamism_cp * eval::sharpness(board)) as i32;
+    let bonus = if engine_is_white { bonus } else { -bonus };
+    misc::cp_to_eval(misc::eval_to_cp(eval) + bonus)
+}
+
+// PUCT: the child's eval plus exploration shared out by the policy's priors,
+// shrinking as the child's own visits grow.  Visits count nodes, which
+// playouts add a whole position's worth of at a time.
+fn mcts_score(
+    tree: &Tree,
+    id: NodeId,
+    mcts_explore: i32,
+    parent_visits: u32,
+    is_w_move: bool,
+) -> f32 {
+    let node = tree.node(id);
+    let eval = tree.value(id).eval();
+    let visits = node.visits();
+    let threads = node.threads();
     // Every mcts_explore increase of 10 will double the effect.
     // Mcts_Explore scaling is centered at 50
-    let scale = 1.0718_f32.powf((mcts_explore as f32) - 50.0);
+    let c_puct = PUCT_BASE * 1.0718_f32.powf((mcts_explore as f32) - 50.0);
+    let explore = c_puct * node.prior() * (parent_visits as f32).sqrt()
+        / ((visits as f32) + AVG_CHILD_COUNT * (threads as f32));
 
     if is_w_move {
-        eval + scale * explore
+        eval + explore
     } else {
-        (1.0 - eval) + scale * explore
+        (1.0 - eval) + explore
     }
 }
 
-fn decr_proc_threads(node: &Arc<Node>) {
-    let mut new_node = Arc::clone(node);
-    loop {
-        *new_node.proc_threads.write().unwrap() -= 1;
-        match new_node.parent.upgrade() {
-            Some(a) => new_node = Arc::clone(&a),
-            None => break,
-        }
+fn decr_proc_threads(tree: &Tree, path: &[NodeId]) {
+    for &id in path.iter() {
+        tree.node(id).remove_thread();
     }
 }
 
-fn propogate_values(leaf: &Arc<Node>) {
-    let mut node = Arc::clone(leaf);
+// Every node on the path gains the visits of the leaf's new children.  The
+// eval, ending and depth are worked out again from the children, the eval by
+// the backup operator, on the node
+// holding them when the path goes through a link.  Other nodes linking to
+// that position read the new values from it, though their parents only take
+// them in the next time a playout passes through.
+fn propogate_values(tree: &Tree, path: &[NodeId], draw_eval: f32, backup: Backup) {
+    let new_nodes = tree.children(*path.last().unwrap()).len() as u32;
+    let mut samples = Vec::new();
+    for (ply, &id) in path.iter().enumerate().rev() {
+        let edge = tree.node(id);
+        let is_w_move = tree.board.is_w_move == (ply % 2 == 0);
+        edge.remove_thread();
+        edge.add_visits(new_nodes);
 
-    loop {
-        {
-            let children = node.children.read().unwrap();
+        let node = tree.value(id);
+        let children = tree.children(id);
+        let length = children.len();
+        // Checkmate and stalemate were scored when the leaf was bloomed
+        if length == 0 {
+            continue;
+        }
 
-            *node.proc_threads.write().unwrap() -= 1;
+        let mut w_wins = 0;
+        let mut b_wins = 0;
+        let mut draws = 0;
+        let mut fast_w_win = u32::MAX;
+        let mut slow_w_win = 0;
+        let mut fast_b_win = u32::MAX;
+        let mut slow_b_win = 0;
+        samples.clear();
+        for c in children {
+            let child = tree.value(c);
+            samples.push((tree.node(c).visits(), mover_eval(child, true)));
 
-            let mut new_visits = 1;
-            let length = children.len();
-            let mut w_wins = 0;
-            let mut b_wins = 0;
-            let mut draws = 0;
-            let mut fast_w_win = u32::MAX;
-            let mut slow_w_win = 0;
-            let mut fast_b_win = u32::MAX;
-            let mut slow_b_win = 0;
-            let mut new_eval = if length > 0 {
-                children[0].eval.read().unwrap().deref().clone()
-            } else {
-                0.5
-            };
-            for child in children.iter() {
-                // Update new eval
-                let c_eval = child.eval.read().unwrap();
-                let eval = node.eval.read().unwrap();
-                if node.board.is_w_move {
-                    if *c_eval > new_eval {
-                        new_eval = *c_eval;
-                        //println!("Replacing eval {} with {} for {} from {}", *eval, new_eval, node.board.to_string(), child.board.to_string());
+            // Update parent depth
+            node.raise_depth(child.depth() + 1);
+
+            // Sample child endings
+            if let Some(e) = child.ending() {
+                match e {
+                    Ending::Draw => draws += 1,
+                    Ending::WhiteWin(m) => {
+                        w_wins += 1;
+                        fast_w_win = cmp::min(fast_w_win, m + 1);
+                        slow_w_win = cmp::max(slow_w_win, m + 1);
                     }
-                } else {
-                    if *c_eval < new_eval {
-                        new_eval = *c_eval;
-                        //println!("Replacing eval {} with {} for {} from {}", *eval, new_eval, node.board.to_string(), child.board.to_string());
+                    Ending::BlackWin(m) => {
+                        b_wins += 1;
+                        fast_b_win = cmp::min(fast_b_win, m + 1);
+                        slow_b_win = cmp::max(slow_b_win, m + 1);
                     }
                 }
-                drop(eval);
-                drop(c_eval);
-
-                // Update parent visits
-                new_visits += *child.visits.read().unwrap();
-
-                // Update parent depth
-                let c_depth = child.depth.read().unwrap();
-                let mut depth = node.depth.write().unwrap();
-                if *c_depth + 1 > *depth {
-                    *depth = *c_depth + 1;
-                }
-                drop(depth);
-                drop(c_depth);
+            }
+        }
+        node.set_eval(backup.eval(&samples, is_w_move));
 
-                // Sample child endings
-                match *child.ending.read().unwrap() {
-                    Some(e) => match e {
-                        Ending::Draw => draws += 1,
-                        Ending::WhiteWin(m) => {
-                            w_wins += 1;
-                            if m + 1 < fast_w_win {
-                                fast_w_win = m + 1;
-                            } else if m + 1 > slow_w_win {
-                                slow_w_win = m + 1;
-                            }
-                        }
-                        Ending::BlackWin(m) => {
-                            b_wins += 1;
-                            if m + 1 < fast_b_win {
-                                fast_b_win = m + 1;
-                            } else if m + 1 > slow_b_win {
-                                slow_b_win = m + 1;
-                            }
-                        }
-                    },
-                    None => (),
-                }
+        // Update parent ending
+        if is_w_move {
+            if w_wins > 0 {
+                node.set_ending(Some(Ending::WhiteWin(fast_w_win)));
+            } else if draws > 0 && draws == length - b_wins {
+                node.set_ending(Some(Ending::Draw));
+                node.set_eval(draw_eval);
+            } else if b_wins == length {
+                node.set_ending(Some(Ending::BlackWin(slow_b_win)));
             }
-            *node.visits.write().unwrap() = new_visits;
-            *node.eval.write().unwrap() = new_eval;
-
-            // Update parent ending
-            // Checkmate and Stalemate calculation for leaf nodes with no children
-            if length == 0 {
-                if node.board.is_w_move
-                    && move_gen::is_attacked(&node.board, false, node.board.w_k_bb)
-                {
-                    *node.ending.write().unwrap() = Some(Ending::BlackWin(0));
-                } else if !node.board.is_w_move
-                    && move_gen::is_attacked(&node.board, true, node.board.b_k_bb)
-                {
-                    *node.ending.write().unwrap() = Some(Ending::WhiteWin(0));
-                } else {
-                    *node.ending.write().unwrap() = Some(Ending::Draw);
-                }
-            } else {
-                // Not a leaf node with no children, propogate values
-                if node.board.is_w_move {
-                    if w_wins > 0 {
-                        *node.ending.write().unwrap() = Some(Ending::WhiteWin(fast_w_win));
-                    } else if draws > 0 && draws == length - b_wins {
-                        *node.ending.write().unwrap() = Some(Ending::Draw);
-                    } else if b_wins == length {
-                        *node.ending.write().unwrap() = Some(Ending::BlackWin(slow_b_win));
-                    }
-                } else {
-                    if b_wins > 0 {
-                        *node.ending.write().unwrap() = Some(Ending::BlackWin(fast_b_win));
-                    } else if draws > 0 && draws == length - w_wins {
-                        *node.ending.write().unwrap() = Some(Ending::Draw);
-                    } else if w_wins == length {
-                        *node.ending.write().unwrap() = Some(Ending::WhiteWin(slow_w_win));
-                    }
-                }
+        } else {
+            if b_wins > 0 {
+                node.set_ending(Some(Ending::BlackWin(fast_b_win)));
+            } else if draws > 0 && draws == length - w_wins {
+                node.set_ending(Some(Ending::Draw));
+                node.set_eval(draw_eval);
+            } else if w_wins == length {
+                node.set_ending(Some(Ending::WhiteWin(slow_w_win)));
             }
         }
 
-        // Move to the parent
-        match node.parent.upgrade() {
-            Some(n) => node = Arc::clone(&n),
-            None => break,
+        // A link keeps the values it last saw in case it is cut by a prune
+        if !std::ptr::eq(edge, node) {
+            edge.set_eval(node.eval());
+            edge.set_ending(node.ending());
+            edge.raise_depth(node.depth());
         }
     }
 }
 
+// root_moves are the root children the search may choose from, in any order
 fn stop_searching(
-    root: &Arc<Node>,
+    tree: &Tree,
+    root_moves: &[NodeId],
     start_time: &Instant,
     go_parms: &UciGo,
     move_overhead: i32,
-    move_speed: i32,
-    mcts_hash: i32,
+    time_manager: Option<&mut TimeManager>,
 ) -> bool {
-    if root.children.read().unwrap().len() < 2 {
-        return true;
+    // Infinite and ponder searches run until the GUI tells them otherwise
+    if go_parms.infinite || go_parms.ponder {
+        return false;
     }
-    if root
-        .children
-        .read()
-        .unwrap()
-        .iter()
-        .all(|x| x.ending.read().unwrap().is_some())
-    {
+    let root = tree.node(tree.root());
+    // A forced move still has to be proven when searching for mate
+    if root_moves.len() < 2 && go_parms.mate.is_none() {
         return true;
     }
-    if u64::try_from(*root.visits.read().unwrap()).unwrap() * BYTES_PER_NODE
-        > u64::try_from(mcts_hash).unwrap() * 1048576
-    {
+    if root_moves.iter().all(|&x| tree.value(x).ending().is_some()) {
         return true;
     }
-    match *go_parms {
-        Time {
-            wtime,
-            winc,
-            btime,
-            binc,
-            movestogo,
-        } => {
-            let (time_left, time_inc) = if root.board.is_w_move {
-                (wtime, winc)
+
+    if let Some(moves) = go_parms.mate {
+        if mate_found(tree, moves) {
+            return true;
+        }
+    }
+    if let Some(plies) = go_parms.depth {
+        if root.depth() > plies {
+            return true;
+        }
+    }
+    if let Some(count) = go_parms.nodes {
+        if root.visits() > count {
+            return true;
+        }
+    }
+    let elapsed = start_time.elapsed().as_millis();
+    if let Some(mseconds) = go_parms.movetime {
+        if elapsed + move_overhead as u128 >= u128::from(mseconds) {
+            return true;
+        }
+    }
+
+    let time_manager = match time_manager {
+        Some(tm) => tm,
+        None => return false,
+    };
+    if !time_manager.update_due(elapsed) {
+        return time_manager.out_of_time(elapsed);
+    }
+    if let Some((leader, margin)) = visit_leader(tree, root_moves) {
+        time_manager.update(
+            leader,
+            root.visits(),
+            margin,
+            mover_eval(tree.value(leader), tree.board.is_w_move),
+        );
+    }
+    time_manager.out_of_time(elapsed)
+}
+
+// The most visited root move and how far it leads the runner up, as a share
+// of its own visits
+fn visit_leader(tree: &Tree, root_moves: &[NodeId]) -> Option<(NodeId, f32)> {
+    let mut leader: Option<(NodeId, u32)> = None;
+    let mut runner_up = 0;
+    for &child in root_moves.iter() {
+        let visits = tree.node(child).visits();
+        match leader {
+            Some((_, most)) if visits <= most => runner_up = cmp::max(runner_up, visits),
+            _ => {
+                runner_up = leader.map_or(0, |(_, most)| most);
+                leader = Some((child, visits));
+            }
+        }
+    }
+    leader.map(|(child, most)| (child, (most - runner_up) as f32 / most.max(1) as f32))
+}
+
+// Frees at least the given number of bytes, when the tree has them, by
+// collapsing subtrees into their roots.  Subtrees the search can no longer
+// enter because their result is proven go first, then the least visited.
+// Returns the bytes released.
+fn prune(tree: &Tree, bytes: usize) -> usize {
+    let gate = tree.exclusive();
+    let root = tree.root();
+    let mut candidates = Vec::new();
+    let mut stack: Vec<(NodeId, bool)> = vec![(root, tree.board.is_w_move)];
+    while let Some((id, is_w_move)) = stack.pop() {
+        // A link's subtree belongs to the node it links to
+        if tree.resolve(id) != id || tree.children(id).is_empty() {
+            continue;
+        }
+        stack.extend(tree.children(id).map(|c| (c, !is_w_move)));
+        if id == root {
+            continue;
+        }
+        let node = tree.node(id);
+        let reachable = node.ending().is_none();
+        candidates.push((reachable, node.visits(), id, is_w_move));
+    }
+    // Ancestors have more visits, so they are mostly collapsed after their
+    // descendants.  Links can send more visits below a node than through it,
+    // but nodes already freed are then left alone.
+    candidates.sort_by_key(|(reachable, visits, _, _)| (*reachable, *visits));
+
+    let mut freed = 0;
+    for (_, _, id, is_w_move) in candidates.iter() {
+        if freed >= bytes {
+            break;
+        }
+        freed += collapse(tree, &gate, *id, *is_w_move);
+    }
+    tree.sweep(&gate);
+    freed
+}
+
+// Drops a node's children and folds their visits into the node, which keeps
+// its eval and becomes a leaf again.  Proven nodes only drop what lies below
+// their children besides the best one, so mating lines can still be printed.
+fn collapse(tree: &Tree, gate: &RwLockWriteGuard<()>, id: NodeId, is_w_move: bool) -> usize {
+    if tree.node(id).ending().is_some() {
+        return ranked_children(tree, id, is_w_move)
+            .into_iter()
+            .skip(1)
+            .map(|c| drop_children(tree, gate, c))
+            .sum();
+    }
+    drop_children(tree, gate, id)
+}
+
+fn drop_children(tree: &Tree, gate: &RwLockWriteGuard<()>, id: NodeId) -> usize {
+    // Links own no children
+    if tree.resolve(id) != id {
+        return 0;
+    }
+    let visits = tree.position_visits(id);
+    tree.node(id).set_pruned_visits(visits - 1);
+    tree.collapse(gate, id)
+}
+
+// True once the side to move is proven to mate within the given number of
+// moves.  Endings count plies, so mate in n is at most 2n - 1 of them.
+fn mate_found(tree: &Tree, moves: u32) -> bool {
+    match (tree.node(tree.root()).ending(), tree.board.is_w_move) {
+        (Some(Ending::WhiteWin(plies)), true) | (Some(Ending::BlackWin(plies)), false) => {
+            plies < 2 * moves
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn new_tree(fen: &str) -> Tree {
+        Tree::new(board::Board::new(fen), Vec::new())
+    }
+
+    // Blooms a node on static evals with neutral contempt and style.  line
+    // holds the hashes of the positions from the root down to the node's
+    // parent.
+    fn bloom_node(tree: &Tree, id: NodeId, board: &board::Board, line: &[u64]) {
+        bloom_styled(tree, id, board, line, 0.5, 0.0, 0);
+    }
+
+    fn bloom_styled(
+        tree: &Tree,
+        id: NodeId,
+        board: &board::Board,
+        line: &[u64],
+        draw_eval: f32,
+        dynamism_cp: f32,
+        quiescence: u32,
+    ) {
+        assert!(tree.claim(id));
+        let mut line = line.to_vec();
+        line.push(board.hash);
+        assert!(bloom(
+            tree,
+            id,
+            board,
+            &line,
+            draw_eval,
+            dynamism_cp,
+            quiescence,
+        ));
+    }
+
+    fn child(tree: &Tree, id: NodeId, mov: &str) -> NodeId {
+        tree.children(id)
+            .find(|&c| tree.node(c).mov().to_string() == mov)
+            .unwrap()
+    }
+
+    fn is_draw(tree: &Tree, id: NodeId) -> bool {
+        matches!(tree.node(id).ending(), Some(Ending::Draw))
+    }
+
+    fn playouts(tree: &Tree, count: usize, searchmoves: &[String]) {
+        graph_playouts(tree, count, searchmoves, false);
+    }
+
+    fn graph_playouts(tree: &Tree, count: usize, searchmoves: &[String], transpositions: bool) {
+        for _ in 0..count {
+            let path = find_and_bloom_leaf_node(tree, 50, 0.5, 0.0, 0, searchmoves, transpositions)
+                .unwrap();
+            propogate_values(tree, &path, 0.5, Backup::Minimax);
+        }
+    }
+
+    // Links in the tree, walking each subtree once
+    fn count_links(tree: &Tree) -> usize {
+        let mut links = 0;
+        let mut stack = vec![tree.root()];
+        while let Some(id) = stack.pop() {
+            if tree.resolve(id) != id {
+                links += 1;
             } else {
-                (btime, binc)
-            };
-            let time_inc = match time_inc {
-                Some(t) => t,
-                None => 0,
-            };
+                stack.extend(tree.children(id));
+            }
+        }
+        links
+    }
 
-            let m_to_go = match movestogo {
-                Some(s) => s,
-                None => cmp::min(
-                    misc::eval_to_movestogo(*root.eval.read().unwrap()),
-                    MAX_GAME_LENGTH,
-                ),
-            };
+    fn best_move(tree: &Tree, skill: i32, searchmoves: &[String], rng_state: &mut u64) -> String {
+        let root = tree.root();
+        let best = get_bestmove(
+            tree,
+            root,
+            tree.board.is_w_move,
+            skill,
+            searchmoves,
+            rng_state,
+        );
+        tree.node(best.unwrap()).mov().to_string()
+    }
+
+    #[test]
+    fn repetition_inside_tree() {
+        let tree = new_tree(STARTPOS);
+        let mut id = tree.root();
+        let mut board = tree.board;
+        let mut line = Vec::new();
+        for mov in ["g1f3", "g8f6", "f3g1"].iter() {
+            bloom_node(&tree, id, &board, &line);
+            let next = child(&tree, id, mov);
+            assert!(!is_draw(&tree, next));
+            line.push(board.hash);
+            board = tree.child_board(&board, next);
+            id = next;
+        }
+        bloom_node(&tree, id, &board, &line);
+        assert!(is_draw(&tree, child(&tree, id, "f6g8")));
+        assert!(!is_draw(&tree, child(&tree, id, "b8c6")));
+    }
+
+    #[test]
+    fn repetition_of_game_history() {
+        let mut board = board::Board::new(STARTPOS);
+        let mut history = Vec::new();
+        for mov in ["g1f3", "g8f6", "f3g1"].iter() {
+            history.push(board.hash);
+            board.do_move(mov);
+        }
+        let tree = Tree::new(board, Vec::new());
+        bloom_node(&tree, tree.root(), &board, &[]);
+        assert!(!is_draw(&tree, child(&tree, tree.root(), "f6g8")));
+
+        let tree = Tree::new(board, history);
+        bloom_node(&tree, tree.root(), &board, &[]);
+        assert!(is_draw(&tree, child(&tree, tree.root(), "f6g8")));
+        assert!(!is_draw(&tree, child(&tree, tree.root(), "b8c6")));
+    }
+
+    #[test]
+    fn skill_move_selection() {
+        let tree = new_tree(STARTPOS);
+        let root = tree.root();
+        bloom_node(&tree, root, &tree.board, &[]);
+        for c in tree.children(root) {
+            tree.node(c).set_visits(100);
+            tree.node(c).set_eval(0.5);
+        }
+        tree.node(child(&tree, root, "e2e4")).set_visits(500);
+        tree.node(child(&tree, root, "g2g4")).set_eval(0.05);
+
+        let mut rng_state = 0x5eed;
+        let mut picked = Vec::new();
+        for _ in 0..200 {
+            assert_eq!(best_move(&tree, 100, &[], &mut rng_state), "e2e4");
+
+            let mov = best_move(&tree, 1, &[], &mut rng_state);
+            assert_ne!(mov, "g2g4");
+            if !picked.contains(&mov) {
+                picked.push(mov);
+            }
+        }
+        assert!(picked.len() > 5, "{:?}", picked);
+
+        assert_eq!(misc::elo_to_skill(0), 1);
+        assert_eq!(misc::elo_to_skill(misc::MIN_ELO), 1);
+        assert_eq!(misc::elo_to_skill(misc::MAX_ELO), 100);
+    }
 
-            let need_extension = needs_extension(root);
-            let speed = 4.0_f32.powf((move_speed as f32) / 50.0 - 1.0);
-            let time_allowed = (time_left.unwrap() - u32::try_from(move_overhead).unwrap()) as f32;
-            let time_ration = time_allowed / (m_to_go as f32 * speed);
+    #[test]
+    fn contempt_changes_draw_choice() {
+        // Black can repeat the position with f6g8
+        let mut board = board::Board::new(STARTPOS);
+        let mut history = Vec::new();
+        for mov in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"].iter() {
+            history.push(board.hash);
+            board.do_move(mov);
+        }
+
+        let best = |contempt: i32| {
+            let tree = Tree::new(board, history.clone());
+            let root = tree.root();
+            bloom_styled(&tree, root, &board, &[], draw_eval(contempt, false), 0.0, 0);
+            // Every other move leaves white slightly better
+            for c in tree.children(root) {
+                if tree.node(c).ending().is_none() {
+                    tree.node(c).set_eval(0.52);
+                }
+            }
+            assert!(is_draw(&tree, child(&tree, root, "f6g8")));
+            best_move(&tree, 100, &[], &mut 0)
+        };
+
+        assert_eq!(best(0), "f6g8");
+        assert_eq!(best(-50), "f6g8");
+        assert_ne!(best(50), "f6g8");
+
+        // Near level children take part of the draw's shift when bloomed
+        let e5_eval = |contempt: i32| {
+            let tree = Tree::new(board, history.clone());
+            let root = tree.root();
+            bloom_styled(&tree, root, &board, &[], draw_eval(contempt, false), 0.0, 0);
+            tree.node(child(&tree, root, "e7e5")).eval()
+        };
+        let shift = e5_eval(50) - e5_eval(0);
+        assert!(shift > 0.0 && shift <= draw_eval(50, false) - 0.5);
+        assert!(e5_eval(-50) < e5_eval(0));
+
+        // so black playing for a win leaves a dead level position for a
+        // slightly worse one that is further from a draw
+        let best = |contempt: i32| {
+            let tree = Tree::new(board, history.clone());
+            let root = tree.root();
+            bloom_styled(&tree, root, &board, &[], draw_eval(contempt, false), 0.0, 0);
+            for c in tree.children(root) {
+                let eval = match tree.node(c).mov().to_string().as_str() {
+                    "e7e5" => 0.5,
+                    "d7d5" => misc::cp_to_eval(30),
+                    _ => 0.6,
+                };
+                tree.node(c)
+                    .set_eval(score_contempt(eval, draw_eval(contempt, false)));
+                tree.node(c).set_ending(None);
+            }
+            best_move(&tree, 100, &[], &mut 0)
+        };
+        assert_eq!(best(0), "e7e5");
+        assert_eq!(best(100), "d7d5");
+        assert_eq!(score_contempt(0.9, draw_eval(100, false)), 0.9);
+
+        assert_eq!(draw_eval(0, true), 0.5);
+        assert!(draw_eval(50, true) < 0.5);
+        assert!(draw_eval(50, false) > 0.5);
+    }
+
+    #[test]
+    fn dynamism_changes_root_preference() {
+        let fens = [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/4P3/2N2b2/PPPPQPPP/R1B1KBNR w KQkq - 0 5",
+            "rnbqk2r/pppp1ppp/5n2/4p3/1b2P3/2N5/PPPP1PPP/R1BQKBNR w KQkq - 2 4",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq d3 0 3",
+            "rnbqkb1r/ppp2ppp/4pn2/3p4/2PP4/2N5/PP2PPPP/R1BQKBNR w KQkq - 2 4",
+            "r2qkbnr/ppp2ppp/2np4/4p3/2B1P1b1/5N2/PPPP1PPP/RNBQ1RK1 w kq - 2 5",
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+            "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQK2R w KQkq - 4 5",
+            "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+            "rnbqkb1r/pp2pppp/3p1n2/8/3NP3/8/PPP2PPP/RNBQKB1R w KQkq - 1 5",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "rnbqkb1r/pppp1ppp/5n2/4p3/2B1P3/8/PPPP1PPP/RNBQK1NR w KQkq - 2 3",
+            "r1b1kbnr/pppp1ppp/2n5/4p1q1/4P3/3P1N2/PPP2PPP/RNBQKB1R w KQkq - 1 4",
+        ];
+
+        // Mean sharpness of the five children each root likes best on eval
+        let preferred_sharpness = |dynamism: i32| -> f32 {
+            fens.iter()
+                .map(|fen| {
+                    let tree = new_tree(fen);
+                    let root = tree.root();
+                    bloom_styled(&tree, root, &tree.board, &[], 0.5, dynamism_cp(dynamism), 0);
+                    let mut ranked: Vec<(f32, f32)> = tree
+                        .children(root)
+                        .map(|c| {
+                            (
+                                mover_eval(tree.value(c), tree.board.is_w_move),
+                                eval::sharpness(&tree.child_board(&tree.board, c)),
+                            )
+                        })
+                        .collect();
+                    ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+                    ranked.iter().take(5).map(|(_, sharp)| sharp).sum::<f32>() / 5.0
+                })
+                .sum::<f32>()
+                / fens.len() as f32
+        };
+
+        assert_eq!(dynamism_cp(50), 0.0);
+        assert!(
+            preferred_sharpness(100) > preferred_sharpness(1),
+            "{} <= {}",
+            preferred_sharpness(100),
+            preferred_sharpness(1)
+        );
+    }
+
+    #[test]
+    fn stop_searching_honours_every_limit() {
+        let tree = new_tree(STARTPOS);
+        playouts(&tree, 100, &[]);
+        let moves: Vec<NodeId> = tree.children(tree.root()).collect();
+        let start_time = Instant::now();
+        let stops = |go_parms: UciGo| {
+            let mut time_manager = TimeManager::new(&go_parms, true, 0.5, 0, 50);
+            stop_searching(
+                &tree,
+                &moves,
+                &start_time,
+                &go_parms,
+                0,
+                time_manager.as_mut(),
+            )
+        };
 
-            if need_extension {
-                return cmp::min(
-                    ((time_ration * TIME_EXTENSION_MULT_MAX) as u32 + time_inc) as u128,
-                    time_allowed as u128,
-                ) < start_time.elapsed().as_millis();
+        let roomy = UciGo {
+            wtime: Some(3_600_000),
+            depth: Some(100),
+            nodes: Some(1_000_000),
+            movetime: Some(3_600_000),
+            ..Default::default()
+        };
+        assert!(!stops(roomy.clone()));
+        assert!(!stops(UciGo::default()));
+
+        // Any single limit that has been reached ends the search
+        assert!(stops(UciGo {
+            depth: Some(0),
+            ..roomy.clone()
+        }));
+        assert!(stops(UciGo {
+            nodes: Some(50),
+            ..roomy.clone()
+        }));
+        assert!(stops(UciGo {
+            movetime: Some(0),
+            ..roomy.clone()
+        }));
+        assert!(stops(UciGo {
+            wtime: Some(0),
+            ..roomy.clone()
+        }));
+        // Only the side to move's clock counts
+        assert!(!stops(UciGo {
+            btime: Some(0),
+            ..roomy.clone()
+        }));
+
+        // Infinite searches ignore every limit
+        assert!(!stops(UciGo {
+            depth: Some(0),
+            movetime: Some(0),
+            infinite: true,
+            ..roomy
+        }));
+    }
+
+    #[test]
+    fn searchmoves_restricts_root() {
+        let tree = new_tree(STARTPOS);
+        let searchmoves = vec!["a2a3".to_string(), "h2h3".to_string()];
+        playouts(&tree, 200, &searchmoves);
+
+        // Excluded moves stay in the tree but are never explored
+        assert_eq!(tree.children(tree.root()).len(), 20);
+        for c in tree.children(tree.root()) {
+            let visits = tree.node(c).visits();
+            if is_searched(&tree, c, &searchmoves) {
+                assert!(visits > 50);
             } else {
-                return cmp::min(
-                    (time_ration as u32 + time_inc) as u128,
-                    time_allowed as u128,
-                ) < start_time.elapsed().as_millis();
+                assert_eq!(visits, 1);
             }
         }
-        Depth { plies } => {
-            let depth = root.depth.read().unwrap();
-            *depth > plies
+
+        assert_eq!(root_moves(&tree, &searchmoves).len(), 2);
+        assert_eq!(root_moves(&tree, &[]).len(), 20);
+        for skill in [1, 100].iter() {
+            let best = best_move(&tree, *skill, &searchmoves, &mut 7);
+            assert!(searchmoves.contains(&best));
         }
-        Nodes { count } => {
-            let visits = root.visits.read().unwrap();
-            *visits > count
+    }
+
+    #[test]
+    fn mate_search() {
+        // 1. Rd8+ Rxd8 2. Rxd8#
+        let tree = new_tree("r5k1/5ppp/8/8/8/8/3R1PPP/3R2K1 w - - 0 1");
+        let mut iterations = 0;
+        while !mate_found(&tree, 2) {
+            playouts(&tree, 1, &[]);
+            iterations += 1;
+            assert!(iterations < 100_000, "mate in 2 not proven");
         }
-        Movetime { mseconds } => {
-            mseconds > u32::try_from(start_time.elapsed().as_millis()).unwrap()
+        assert!(!mate_found(&tree, 1));
+
+        let best = root_moves(&tree, &[])[0];
+        assert!(matches!(
+            tree.node(best).ending(),
+            Some(Ending::WhiteWin(2))
+        ));
+        assert_eq!(get_pv(&tree, best, false), "d2d8 a8d8 d1d8");
+
+        assert_eq!(uci_mate(3, true), "mate 2");
+        assert_eq!(uci_mate(1, true), "mate 1");
+        assert_eq!(uci_mate(2, false), "mate -1");
+    }
+
+    #[test]
+    fn prune_collapses_subtrees() {
+        let tree = new_tree(STARTPOS);
+        playouts(&tree, 3000, &[]);
+
+        let before = tree.bytes();
+        let root = tree.node(tree.root());
+        let visits = root.visits();
+        let leader = visit_leader(&tree, &root_moves(&tree, &[])).unwrap().0;
+        let leader_visits = tree.node(leader).visits();
+
+        let freed = prune(&tree, before / 2);
+        assert!(freed >= before / 2);
+        assert_eq!(tree.bytes(), before - freed);
+
+        // Collapsed subtrees keep their statistics
+        assert_eq!(tree.children(tree.root()).len(), 20);
+        assert_eq!(root.visits(), visits);
+        assert_eq!(tree.node(leader).visits(), leader_visits);
+
+        // and the search carries on from them, reusing the freed nodes
+        playouts(&tree, 500, &[]);
+        assert!(root.visits() > visits);
+        assert!(tree.node(leader).visits() >= leader_visits);
+        assert!(tree.bytes() < before);
+    }
+
+    #[test]
+    fn prune_keeps_proven_lines() {
+        let tree = new_tree("r5k1/5ppp/8/8/8/8/3R1PPP/3R2K1 w - - 0 1");
+        while !mate_found(&tree, 2) {
+            playouts(&tree, 1, &[]);
         }
-        Infinite => false,
+
+        assert!(prune(&tree, usize::MAX) > 0);
+        let best = root_moves(&tree, &[])[0];
+        assert_eq!(get_pv(&tree, best, false), "d2d8 a8d8 d1d8");
+        assert!(mate_found(&tree, 2));
     }
-}
 
-fn needs_extension(root: &Arc<Node>) -> bool {
-    root.sort_children();
-    let children = root.children.read().unwrap();
+    #[test]
+    fn ending_packing() {
+        let endings = [
+            None,
+            Some(Ending::Draw),
+            Some(Ending::WhiteWin(0)),
+            Some(Ending::WhiteWin(7)),
+            Some(Ending::BlackWin(0)),
+            Some(Ending::BlackWin(u32::MAX >> 2)),
+        ];
+        for ending in endings.iter() {
+            let node = Node::default();
+            node.set_ending(*ending);
+            assert_eq!(format!("{:?}", node.ending()), format!("{:?}", ending));
+        }
 
-    if children.len() <= 1 {
-        return false;
+        let node = Node::default();
+        node.set_eval(0.731);
+        assert_eq!(node.eval(), 0.731);
     }
 
-    match *children[0].ending.read().unwrap() {
-        Some(e) => match e {
-            Ending::Draw => return true,
-            _ => return false,
-        },
-        None => (),
+    #[test]
+    fn transpositions_share_positions() {
+        let tree = new_tree(STARTPOS);
+        let line = |moves: &[&str]| {
+            let mut path = vec![tree.root()];
+            let mut board = tree.board;
+            let mut hashes = Vec::new();
+            for mov in moves.iter() {
+                let id = *path.last().unwrap();
+                if tree.children(id).is_empty() {
+                    bloom_node(&tree, id, &board, &hashes);
+                }
+                hashes.push(board.hash);
+                let next = child(&tree, id, mov);
+                board = tree.child_board(&board, next);
+                path.push(next);
+            }
+            hashes.pop();
+            (path, transposition_key(&tree, &hashes, &board))
+        };
+
+        // 1. d4 Nf6 2. c4 and 1. c4 Nf6 2. d4 reach the same position
+        let (_, d4_key) = line(&["d2d4", "g8f6", "c2c4"]);
+        let (d4_path, _) = line(&["d2d4", "g8f6", "c2c4", "e7e6"]);
+        let shared = d4_path[3];
+        tree.add_transposition(d4_key, shared);
+        let (c4_path, c4_key) = line(&["c2c4", "g8f6", "d2d4"]);
+        assert_eq!(c4_key, d4_key);
+        let leaf = c4_path[3];
+        assert_eq!(transposition(&tree, &c4_path, c4_key), Some(shared));
+        assert_eq!(transposition(&tree, &d4_path[..4], d4_key), None);
+
+        assert!(tree.claim(leaf));
+        tree.link(leaf, shared);
+        assert_eq!(tree.resolve(leaf), shared);
+        assert_eq!(tree.children(leaf), tree.children(shared));
+
+        // A playout through one move order changes the eval seen by both
+        for c in tree.children(shared) {
+            tree.node(c).set_eval(0.6);
+        }
+        let mut path = c4_path.clone();
+        path.push(child(&tree, leaf, "e7e6"));
+        tree.node(*path.last().unwrap()).set_eval(0.3);
+        for &id in path.iter() {
+            tree.node(id).add_thread();
+        }
+        let visits = tree.node(shared).visits();
+        propogate_values(&tree, &path, 0.5, Backup::Minimax);
+        assert_eq!(tree.node(shared).eval(), 0.3);
+        assert_eq!(tree.value(leaf).eval(), 0.3);
+        assert_eq!(tree.node(leaf).eval(), 0.3);
+        // while visits stay with the move order that was played
+        assert_eq!(tree.node(shared).visits(), visits);
+        assert_eq!(tree.position_visits(leaf), tree.position_visits(shared));
+
+        // 1. Nf3 Nf6 2. Nc3 Ng8 and 1. Nc3 Nf6 2. Nf3 Ng8 reach the same
+        // position too, where Nb1 repeats only the first and Ng1 only the
+        // second, so their repetition draws keep them apart
+        let (_, nf3_key) = line(&["g1f3", "g8f6", "b1c3", "f6g8"]);
+        let (nf3_path, _) = line(&["g1f3", "g8f6", "b1c3", "f6g8", "c3b1"]);
+        let (_, nc3_key) = line(&["b1c3", "g8f6", "g1f3", "f6g8"]);
+        let (nc3_path, _) = line(&["b1c3", "g8f6", "g1f3", "f6g8", "f3g1"]);
+        assert!(is_draw(&tree, nf3_path[5]));
+        assert!(!is_draw(&tree, child(&tree, nf3_path[4], "f3g1")));
+        assert!(is_draw(&tree, nc3_path[5]));
+        assert!(!is_draw(&tree, child(&tree, nc3_path[4], "c3b1")));
+        assert_ne!(nf3_key, nc3_key);
+        tree.add_transposition(nf3_key, nf3_path[4]);
+        assert_eq!(transposition(&tree, &nc3_path[..5], nc3_key), None);
     }
 
-    if *children[0].eval.read().unwrap() <= *children[1].eval.read().unwrap() {
-        return true;
+    #[test]
+    fn transposition_search_counts_nodes_once() {
+        let tree = new_tree(STARTPOS);
+        graph_playouts(&tree, 3000, &[], true);
+        assert!(count_links(&tree) > 0);
+        let root = tree.node(tree.root());
+        assert_eq!(
+            root.visits() as usize * std::mem::size_of::<Node>(),
+            tree.bytes()
+        );
+
+        // Pruning cuts the links into freed subtrees and the search goes on
+        let visits = root.visits();
+        assert!(prune(&tree, tree.bytes() / 2) > 0);
+        assert_eq!(root.visits(), visits);
+        graph_playouts(&tree, 500, &[], true);
+        assert!(root.visits() > visits);
+    }
+
+    #[test]
+    fn puct_selection() {
+        let tree = new_tree(STARTPOS);
+        let root = tree.root();
+        bloom_node(&tree, root, &tree.board, &[]);
+        for c in tree.children(root) {
+            tree.node(c).set_eval(0.5);
+            tree.node(c).set_prior(0.01);
+        }
+        let e4 = child(&tree, root, "e2e4");
+        let d4 = child(&tree, root, "d2d4");
+        let a3 = child(&tree, root, "a2a3");
+        tree.node(e4).set_prior(0.6);
+        tree.node(d4).set_prior(0.3);
+
+        // Equal children are tried in the order of their priors
+        assert_eq!(select_child(&tree, root, 50, true, &[]), Some(e4));
+        tree.node(e4).set_visits(100);
+        assert_eq!(select_child(&tree, root, 50, true, &[]), Some(d4));
+
+        // Once every child is visited a better eval beats a better prior only
+        // when exploration is turned down
+        for c in tree.children(root) {
+            tree.node(c).set_visits(100);
+        }
+        tree.node(a3).set_eval(0.8);
+        assert_eq!(select_child(&tree, root, 50, true, &[]), Some(e4));
+        assert_eq!(select_child(&tree, root, 1, true, &[]), Some(a3));
+
+        // Black picks the eval best for black
+        let h3 = child(&tree, root, "h2h3");
+        tree.node(h3).set_eval(0.2);
+        assert_eq!(select_child(&tree, root, 1, false, &[]), Some(h3));
+
+        // Threads already searching a child push others forward
+        for _ in 0..3 {
+            tree.node(e4).add_thread();
+        }
+        assert_eq!(select_child(&tree, root, 50, true, &[]), Some(d4));
+
+        // Resolved children and moves outside searchmoves are never picked
+        tree.node(d4).set_ending(Some(Ending::Draw));
+        let searchmoves = vec!["d2d4".to_string(), "g2g3".to_string()];
+        let g3 = child(&tree, root, "g2g3");
+        assert_eq!(select_child(&tree, root, 50, true, &searchmoves), Some(g3));
+    }
+
+    #[test]
+    fn sibling_order_is_total() {
+        // A draw between two unresolved nodes that visits and eval rank in
+        // opposite orders
+        let stats = [
+            Stats {
+                visits: 5,
+                eval: 0.5,
+                ending: Some(Ending::Draw),
+            },
+            Stats {
+                visits: 10,
+                eval: 0.4,
+                ending: None,
+            },
+            Stats {
+                visits: 1,
+                eval: 0.6,
+                ending: None,
+            },
+            Stats {
+                visits: 3,
+                eval: 1.0,
+                ending: Some(Ending::WhiteWin(4)),
+            },
+        ];
+        for is_w_move in [true, false] {
+            for a in &stats {
+                for b in &stats {
+                    for c in &stats {
+                        let ab = a.compare(b, is_w_move);
+                        if ab != Ordering::Less && b.compare(c, is_w_move) == ab {
+                            assert_eq!(a.compare(c, is_w_move), ab);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn backup_operators() {
+        let tree = new_tree(STARTPOS);
+        let root = tree.root();
+        bloom_node(&tree, root, &tree.board, &[]);
+        for c in tree.children(root) {
+            tree.node(c).set_eval(0.5);
+        }
+        // A well searched good move and a barely searched better looking one
+        let e4 = child(&tree, root, "e2e4");
+        let a3 = child(&tree, root, "a2a3");
+        tree.node(e4).set_visits(100);
+        tree.node(e4).set_eval(0.6);
+        tree.node(a3).set_eval(0.9);
+
+        let backed_up = |backup: Backup| {
+            propogate_values(&tree, &[root], 0.5, backup);
+            tree.node(root).eval()
+        };
+        let minimax = backed_up(Backup::Minimax);
+        let mean = backed_up(Backup::Mean);
+        let power_mean = backed_up(Backup::PowerMean);
+        assert_eq!(minimax, 0.9);
+        assert!(mean > 0.5 && mean < 0.6);
+        assert!(power_mean > mean && power_mean < minimax);
+
+        // Whatever the backup, the most visited move stays the best move
+        assert_eq!(root_moves(&tree, &[])[0], e4);
+
+        // Black's power mean leans toward the children best for black
+        let black = [(1, 0.25), (1, 0.75)];
+        assert_eq!(Backup::Mean.eval(&black, false), 0.5);
+        assert!(Backup::PowerMean.eval(&black, false) < 0.5);
+        assert_eq!(Backup::Minimax.eval(&black, false), 0.25);
+    }
+
+    #[test]
+    fn quiescence_changes_root_choice() {
+        // The root move ranked best on the children's evals alone
+        let best = |fen: &str, quiescence: u32| {
+            let tree = new_tree(fen);
+            let root = tree.root();
+            bloom_styled(&tree, root, &tree.board, &[], 0.5, 0.0, quiescence);
+            let best = root_moves(&tree, &[])[0];
+            tree.node(best).mov().to_string()
+        };
+
+        // Qxd5 grabs a pawn and loses the queen to exd5
+        let fen = "4k3/8/4p3/3p4/8/8/3Q4/4K3 w - - 0 1";
+        assert_eq!(best(fen, 0), "d2d5");
+        assert_ne!(best(fen, 4), "d2d5");
+
+        // Nxb5 takes the rook but is taken back, Nxe6 keeps the bishop
+        let fen = "7k/8/2p1b3/1r6/3N4/8/8/K7 w - - 0 1";
+        assert_eq!(best(fen, 0), "d4b5");
+        assert_eq!(best(fen, 4), "d4e6");
+    }
+
+    #[test]
+    fn stopped_search_still_blooms_root() {
+        // A stop can arrive before the main thread picks up its search
+        let (options, _) = crate::initialize();
+        let tree = Arc::new(new_tree(STARTPOS));
+        search(
+            Arc::clone(&tree),
+            options,
+            Arc::new(Mutex::new(false)),
+            Arc::new(Mutex::new(false)),
+            UciGo {
+                infinite: true,
+                ..Default::default()
+            },
+            true,
+        );
+        assert_eq!(tree.children(tree.root()).len(), 20);
     }
-    false
 }
@@ -0,0 +1,218 @@
+use crate::misc;
+use crate::tree::NodeId;
+use crate::UciGo;
+
+// Bounds on the estimated number of moves left for the side to move
+const MIN_MOVES_TO_GO: u32 = 10;
+const MAX_MOVES_TO_GO: u32 = 60;
+// Most the budget may grow above the optimum time for one move
+const MAX_STRETCH: f32 = 3.0;
+// Share of the remaining clock one move may use unless it is the last before
+// the time control
+const MAX_CLOCK_SHARE: f32 = 0.5;
+// Budget multipliers when the best move has no visit lead and when it has all
+// of the visits
+const MARGIN_SLOW: f32 = 1.4;
+const MARGIN_FAST: f32 = 0.6;
+// Extra budget for each change of best move, up to MAX_BEST_MOVE_CHANGES
+const BEST_MOVE_CHANGE_WEIGHT: f32 = 0.2;
+const MAX_BEST_MOVE_CHANGES: u32 = 5;
+// Extra budget per unit of win probability the best move's eval has drifted
+const EVAL_DRIFT_WEIGHT: f32 = 10.0;
+const MAX_EVAL_DRIFT_BONUS: f32 = 1.0;
+// Root visits needed before best move changes are more than noise
+const MIN_STABLE_VISITS: u32 = 1000;
+// Milliseconds between looks at the root, which is not worth doing on every
+// playout
+const UPDATE_INTERVAL_MS: u128 = 10;
+
+// Decides how long the side to move may think under wtime/btime controls.
+// The optimum time comes from the clock, increment and the moves left, then
+// grows when the search is unsettled and shrinks when one move dominates.
+pub struct TimeManager {
+    optimum: f32,
+    maximum: f32,
+    best_move: Option<NodeId>,
+    best_move_changes: u32,
+    start_eval: Option<f32>,
+    eval_drift: f32,
+    margin: f32,
+    last_update_ms: Option<u128>,
+}
+
+impl TimeManager {
+    // None when the side to move has no clock to manage
+    pub fn new(
+        go_parms: &UciGo,
+        is_w_move: bool,
+        root_eval: f32,
+        move_overhead: i32,
+        move_speed: i32,
+    ) -> Option<TimeManager> {
+        let (time_left, time_inc) = if is_w_move {
+            (go_parms.wtime?, go_parms.winc.unwrap_or_default())
+        } else {
+            (go_parms.btime?, go_parms.binc.unwrap_or_default())
+        };
+        let time_left = time_left.saturating_sub(move_overhead.max(0) as u32) as f32;
+
+        // The game is likely to end before a distant time control
+        let estimate = misc::eval_to_movestogo(root_eval).clamp(MIN_MOVES_TO_GO, MAX_MOVES_TO_GO);
+        let moves_to_go = match go_parms.movestogo {
+            Some(m) => m.clamp(1, estimate),
+            None => estimate,
+        };
+
+        let speed = 4.0_f32.powf((move_speed as f32) / 50.0 - 1.0);
+        let optimum = (time_left / moves_to_go as f32 + time_inc as f32) / speed;
+        let clock_share = if moves_to_go == 1 {
+            1.0
+        } else {
+            MAX_CLOCK_SHARE
+        };
+        let maximum = (optimum * MAX_STRETCH).min(time_left * clock_share);
+
+        Some(TimeManager {
+            optimum: optimum.min(maximum),
+            maximum,
+            best_move: None,
+            best_move_changes: 0,
+            start_eval: None,
+            eval_drift: 0.0,
+            margin: 0.5,
+            last_update_ms: None,
+        })
+    }
+
+    // Whether the root is due another update, which it then counts as done
+    pub fn update_due(&mut self, elapsed_ms: u128) -> bool {
+        match self.last_update_ms {
+            Some(last) if elapsed_ms < last + UPDATE_INTERVAL_MS => false,
+            _ => {
+                self.last_update_ms = Some(elapsed_ms);
+                true
+            }
+        }
+    }
+
+    // Records the state of the root: its most visited child, how far ahead of
+    // the runner up that child is (0 to 1) and its eval for the side to move
+    pub fn update(&mut self, best_move: NodeId, root_visits: u32, margin: f32, eval: f32) {
+        self.margin = margin.clamp(0.0, 1.0);
+        if root_visits < MIN_STABLE_VISITS {
+            self.best_move = Some(best_move);
+            return;
+        }
+
+        if self.best_move != Some(best_move) {
+            if self.best_move.is_some() {
+                self.best_move_changes += 1;
+            }
+            self.best_move = Some(best_move);
+        }
+        let start_eval = *self.start_eval.get_or_insert(eval);
+        self.eval_drift = (eval - start_eval).abs();
+    }
+
+    // Milliseconds the current move may take given what the search has seen
+    pub fn budget(&self) -> f32 {
+        let margin_factor = MARGIN_SLOW - (MARGIN_SLOW - MARGIN_FAST) * self.margin;
+        let instability_factor = 1.0
+            + BEST_MOVE_CHANGE_WEIGHT * self.best_move_changes.min(MAX_BEST_MOVE_CHANGES) as f32
+            + (self.eval_drift * EVAL_DRIFT_WEIGHT).min(MAX_EVAL_DRIFT_BONUS);
+        (self.optimum * margin_factor * instability_factor).min(self.maximum)
+    }
+
+    pub fn out_of_time(&self, elapsed_ms: u128) -> bool {
+        elapsed_ms as f32 >= self.budget()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(wtime: u32, winc: u32, movestogo: Option<u32>) -> UciGo {
+        UciGo {
+            wtime: Some(wtime),
+            winc: Some(winc),
+            movestogo,
+            ..Default::default()
+        }
+    }
+
+    fn manager(go_parms: &UciGo, eval: f32) -> TimeManager {
+        TimeManager::new(go_parms, true, eval, 0, 50).unwrap()
+    }
+
+    #[test]
+    fn moves_remaining_estimate() {
+        // Level positions are far from the end, decisive ones are close to it
+        assert!(misc::eval_to_movestogo(0.5) > MAX_MOVES_TO_GO);
+        assert_eq!(misc::eval_to_movestogo(0.6), 54);
+        assert_eq!(misc::eval_to_movestogo(0.4), 54);
+        assert_eq!(misc::eval_to_movestogo(0.8), 17);
+        assert_eq!(misc::eval_to_movestogo(1.0), 0);
+    }
+
+    #[test]
+    fn budgets_follow_time_controls() {
+        // Only the side to move's clock matters
+        assert!(TimeManager::new(&clock(60_000, 0, None), false, 0.5, 0, 50).is_none());
+
+        // Sudden death spreads the clock over the most moves in a level game
+        let level = manager(&clock(60_000, 0, None), 0.5);
+        assert_eq!(level.budget(), 1000.0);
+        // Decisive positions are expected to end sooner and get more time
+        assert!(manager(&clock(60_000, 0, None), 0.9).budget() > level.budget());
+        // Increments are spent as they arrive
+        assert_eq!(manager(&clock(60_000, 1000, None), 0.5).budget(), 2000.0);
+        // A near time control gets shared between the moves before it
+        assert_eq!(manager(&clock(60_000, 0, Some(20)), 0.5).budget(), 3000.0);
+        // The last move before the control may use nearly all of the clock
+        let last = manager(&clock(6_000, 0, Some(1)), 0.5);
+        assert!(last.budget() > 5_000.0 && last.maximum <= 6_000.0);
+        // A large increment cannot spend more than the clock holds
+        assert!(manager(&clock(1_000, 10_000, None), 0.5).budget() <= 500.0);
+        // No clock left means no time
+        assert!(manager(&clock(0, 0, None), 0.5).out_of_time(0));
+    }
+
+    #[test]
+    fn budgets_follow_search_state() {
+        let go_parms = clock(60_000, 0, None);
+        // Root children standing in for e2e4 and d2d4
+        let (e4, d4) = (1, 2);
+
+        let mut settled = manager(&go_parms, 0.5);
+        settled.update(e4, 5000, 0.9, 0.55);
+        settled.update(e4, 9000, 0.9, 0.55);
+
+        let mut close = manager(&go_parms, 0.5);
+        close.update(e4, 5000, 0.1, 0.55);
+        assert!(close.budget() > settled.budget());
+
+        let mut unstable = manager(&go_parms, 0.5);
+        unstable.update(e4, 5000, 0.1, 0.55);
+        unstable.update(d4, 7000, 0.1, 0.52);
+        unstable.update(e4, 9000, 0.1, 0.50);
+        assert!(unstable.budget() > close.budget());
+        assert!(unstable.budget() <= unstable.maximum);
+
+        // Changes seen in the first few visits are ignored
+        let mut early = manager(&go_parms, 0.5);
+        early.update(e4, 10, 0.1, 0.55);
+        early.update(d4, 20, 0.1, 0.40);
+        early.update(d4, 5000, 0.1, 0.55);
+        assert_eq!(early.budget(), close.budget());
+    }
+
+    #[test]
+    fn updates_are_spaced_out() {
+        let mut tm = manager(&clock(60_000, 0, None), 0.5);
+        assert!(tm.update_due(0));
+        assert!(!tm.update_due(UPDATE_INTERVAL_MS - 1));
+        assert!(tm.update_due(UPDATE_INTERVAL_MS));
+        assert!(!tm.update_due(UPDATE_INTERVAL_MS));
+    }
+}
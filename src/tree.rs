@@ -0,0 +1,779 @@
+use crate::board::Board;
+use crate::move_gen::{self, Move};
+use crate::search::Ending;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::Range;
+use std::sync::atomic::{
+    AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering as AtomicOrdering,
+};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+
+// Index of a node in its tree's arena
+pub type NodeId = u32;
+
+// Nodes are allocated in slabs of 2^SLAB_BITS so they never move once created
+const SLAB_BITS: u32 = 16;
+const SLAB_NODES: usize = 1 << SLAB_BITS;
+const MAX_SLABS: usize = 1 << (32 - SLAB_BITS);
+// Longer than any position's list of legal moves
+const MAX_CHILDREN: usize = 256;
+
+// Node::children states besides a packed range of children
+const UNEXPANDED: u64 = u64::MAX;
+const EXPANDING: u64 = u64::MAX - 1;
+// Marks nodes freed by a prune until the links into them are cut
+const FREED: u64 = u64::MAX - 2;
+// Low half of a link to the node, in the high half, holding the same position
+const LINK: u64 = 0xffff_ff00;
+
+// A position in the search tree.  The board is not stored, it is rebuilt
+// from the root by playing each node's move on the way down.  Statistics are
+// atomics so threads never wait on each other to read or update them.  eval
+// holds the bits of an f32 and ending a packed Ending.
+#[derive(Debug)]
+pub struct Node {
+    visits: AtomicU32,
+    depth: AtomicU32,
+    eval: AtomicU32,
+    ending: AtomicU32,
+    //proc_threads is number of threads searching in this node's children
+    //helps spread out mcts search to prevent thread clumping
+    proc_threads: AtomicU32,
+    //pruned_visits holds the visits of children removed to save memory
+    pruned_visits: AtomicU32,
+    // Policy probability of the move leading here, the bits of an f32
+    prior: AtomicU32,
+    // The packed move from the parent's position to this one
+    mov: AtomicU32,
+    // First child in the high half and the number of children in the low
+    // half, a LINK, or one of UNEXPANDED, EXPANDING and FREED
+    children: AtomicU64,
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        Node {
+            visits: AtomicU32::new(1),
+            depth: AtomicU32::new(0),
+            eval: AtomicU32::new(0.5_f32.to_bits()),
+            ending: AtomicU32::new(Ending::pack(None)),
+            proc_threads: AtomicU32::new(0),
+            pruned_visits: AtomicU32::new(0),
+            prior: AtomicU32::new(1.0_f32.to_bits()),
+            mov: AtomicU32::new(0),
+            children: AtomicU64::new(UNEXPANDED),
+        }
+    }
+}
+
+impl Node {
+    // Turns a free slot into a fresh leaf
+    fn reset(&self, mov: Move, ending: Option<Ending>, eval: f32, prior: f32) {
+        self.set_visits(1);
+        self.set_depth(0);
+        self.set_eval(eval);
+        self.set_ending(ending);
+        self.proc_threads.store(0, AtomicOrdering::Relaxed);
+        self.set_pruned_visits(0);
+        self.prior.store(prior.to_bits(), AtomicOrdering::Relaxed);
+        self.mov.store(mov.pack(), AtomicOrdering::Relaxed);
+        self.children.store(UNEXPANDED, AtomicOrdering::Relaxed);
+    }
+
+    fn copy_from(&self, other: &Node) {
+        for (to, from) in [
+            (&self.visits, &other.visits),
+            (&self.depth, &other.depth),
+            (&self.eval, &other.eval),
+            (&self.ending, &other.ending),
+            (&self.proc_threads, &other.proc_threads),
+            (&self.pruned_visits, &other.pruned_visits),
+            (&self.prior, &other.prior),
+            (&self.mov, &other.mov),
+        ]
+        .iter()
+        {
+            to.store(from.load(AtomicOrdering::Relaxed), AtomicOrdering::Relaxed);
+        }
+        self.children.store(
+            other.children.load(AtomicOrdering::Acquire),
+            AtomicOrdering::Release,
+        );
+    }
+
+    pub fn visits(&self) -> u32 {
+        self.visits.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn set_visits(&self, visits: u32) {
+        self.visits.store(visits, AtomicOrdering::Relaxed);
+    }
+
+    pub fn add_visits(&self, visits: u32) {
+        self.visits.fetch_add(visits, AtomicOrdering::Relaxed);
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn set_depth(&self, depth: u32) {
+        self.depth.store(depth, AtomicOrdering::Relaxed);
+    }
+
+    pub fn raise_depth(&self, depth: u32) {
+        self.depth.fetch_max(depth, AtomicOrdering::Relaxed);
+    }
+
+    pub fn eval(&self) -> f32 {
+        f32::from_bits(self.eval.load(AtomicOrdering::Relaxed))
+    }
+
+    pub fn set_eval(&self, eval: f32) {
+        self.eval.store(eval.to_bits(), AtomicOrdering::Relaxed);
+    }
+
+    pub fn ending(&self) -> Option<Ending> {
+        Ending::unpack(self.ending.load(AtomicOrdering::Relaxed))
+    }
+
+    pub fn set_ending(&self, ending: Option<Ending>) {
+        self.ending
+            .store(Ending::pack(ending), AtomicOrdering::Relaxed);
+    }
+
+    pub fn threads(&self) -> u32 {
+        self.proc_threads.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn add_thread(&self) {
+        self.proc_threads.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+
+    pub fn remove_thread(&self) {
+        self.proc_threads.fetch_sub(1, AtomicOrdering::Relaxed);
+    }
+
+    pub fn pruned_visits(&self) -> u32 {
+        self.pruned_visits.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn set_pruned_visits(&self, visits: u32) {
+        self.pruned_visits.store(visits, AtomicOrdering::Relaxed);
+    }
+
+    pub fn prior(&self) -> f32 {
+        f32::from_bits(self.prior.load(AtomicOrdering::Relaxed))
+    }
+
+    #[cfg(test)]
+    pub fn set_prior(&self, prior: f32) {
+        self.prior.store(prior.to_bits(), AtomicOrdering::Relaxed);
+    }
+
+    // Meaningless for the root, which no move leads to
+    pub fn mov(&self) -> Move {
+        Move::unpack(self.mov.load(AtomicOrdering::Relaxed))
+    }
+}
+
+// Boards from position commands set en passent after every double push while
+// generated ones only do when it can be taken, so positions are matched on
+// their keys, which only include en passent when it can be taken
+fn same_position(a: &Board, b: &Board) -> bool {
+    a.hash == b.hash && a.halfmove_clock == b.halfmove_clock
+}
+
+fn children_of(node: &Node) -> Range<NodeId> {
+    match node.children.load(AtomicOrdering::Acquire) {
+        UNEXPANDED | EXPANDING | FREED => 0..0,
+        word if word & 0xffff_ffff == LINK => 0..0,
+        range => {
+            let first = (range >> 32) as NodeId;
+            first..first + range as u32
+        }
+    }
+}
+
+// The search tree for one root position.  Nodes live in an arena of slabs and
+// a node's children sit next to each other, so they are referenced by a
+// range of indices.  Ranges freed by pruning or rerooting are recycled
+// through free lists kept per range length.
+//
+// With transpositions on the tree becomes a graph.  A leaf reached again by
+// another move order, through the same positions since the last capture or
+// pawn move, links to the node already holding its position, sharing
+// its children, eval and ending.  visits stay with each node itself, so they
+// count the playouts made through that move and no subtree is counted twice.
+pub struct Tree {
+    pub board: Board,
+    //history holds the hashes of the game positions played before the root
+    history: Vec<u64>,
+    root: NodeId,
+    slabs: Box<[OnceLock<Box<[Node]>>]>,
+    // Start of the never used part of the arena
+    next: AtomicU32,
+    // free[n] holds the first index of every free range of n nodes
+    free: Mutex<Vec<Vec<NodeId>>>,
+    free_nodes: AtomicUsize,
+    // Most nodes the arena may hold
+    capacity: AtomicUsize,
+    full: AtomicBool,
+    // Expanded nodes by the transposition key of their position, for linking
+    transpositions: Mutex<HashMap<u64, NodeId>>,
+    linked: AtomicBool,
+    // Ranges left behind by reroot, waiting to be freed
+    detached: Mutex<Vec<Range<NodeId>>>,
+    // The thread freeing them, which holds a reference to the tree
+    freeing: Mutex<Option<JoinHandle<()>>>,
+    // Playouts share the gate while pruning holds it alone, so nodes are
+    // never freed under a thread that is walking through them
+    gate: RwLock<()>,
+}
+
+impl Tree {
+    pub fn new(board: Board, history: Vec<u64>) -> Tree {
+        let mut tree = Tree {
+            board,
+            history,
+            root: 0,
+            slabs: (0..MAX_SLABS).map(|_| OnceLock::new()).collect(),
+            next: AtomicU32::new(0),
+            free: Mutex::new(vec![Vec::new(); MAX_CHILDREN + 1]),
+            free_nodes: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(usize::MAX),
+            full: AtomicBool::new(false),
+            transpositions: Mutex::new(HashMap::new()),
+            linked: AtomicBool::new(false),
+            detached: Mutex::new(Vec::new()),
+            freeing: Mutex::new(None),
+            gate: RwLock::new(()),
+        };
+        tree.root = tree.alloc(1).unwrap();
+        tree
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn history(&self) -> &[u64] {
+        &self.history
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        let slab = self.slabs[(id >> SLAB_BITS) as usize]
+            .get()
+            .expect("Node index outside of the arena!");
+        &slab[id as usize & (SLAB_NODES - 1)]
+    }
+
+    // The node holding the statistics of the node's position, which is the
+    // node itself unless it links to a transposition
+    pub fn resolve(&self, id: NodeId) -> NodeId {
+        match self.node(id).children.load(AtomicOrdering::Acquire) {
+            word if word & 0xffff_ffff == LINK => (word >> 32) as NodeId,
+            _ => id,
+        }
+    }
+
+    // The node's eval, ending and depth live here
+    pub fn value(&self, id: NodeId) -> &Node {
+        self.node(self.resolve(id))
+    }
+
+    // Empty while the node is a leaf or being expanded
+    pub fn children(&self, id: NodeId) -> Range<NodeId> {
+        self.own_children(self.resolve(id))
+    }
+
+    // Leaves out the children of a linked transposition
+    fn own_children(&self, id: NodeId) -> Range<NodeId> {
+        children_of(self.node(id))
+    }
+
+    // Visits made to the node's position through every move leading to it
+    pub fn position_visits(&self, id: NodeId) -> u32 {
+        1 + self.value(id).pruned_visits()
+            + self
+                .children(id)
+                .map(|c| self.node(c).visits())
+                .sum::<u32>()
+    }
+
+    // The positions of the root's descendants are rebuilt by replaying moves
+    pub fn child_board(&self, board: &Board, id: NodeId) -> Board {
+        move_gen::make_move(board, self.node(id).mov())
+    }
+
+    // Held by every playout
+    pub fn playout(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().unwrap()
+    }
+
+    // Waits for running playouts to finish and keeps new ones out
+    pub fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gate.write().unwrap()
+    }
+
+    // Reserves an unexpanded node for the calling thread to expand.  Fails
+    // when it is already expanded or another thread got to it first.
+    pub fn claim(&self, id: NodeId) -> bool {
+        self.node(id)
+            .children
+            .compare_exchange(
+                UNEXPANDED,
+                EXPANDING,
+                AtomicOrdering::Acquire,
+                AtomicOrdering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    // Gives a claimed node one child per move with its ending, eval and
+    // prior.  Fails, giving up the claim, when the arena is full.
+    pub fn expand(&self, id: NodeId, children: &[(Move, Option<Ending>, f32, f32)]) -> bool {
+        let first = match self.alloc(children.len()) {
+            Some(first) => first,
+            None => {
+                self.node(id)
+                    .children
+                    .store(UNEXPANDED, AtomicOrdering::Release);
+                return false;
+            }
+        };
+        for (i, (mov, ending, eval, prior)) in children.iter().enumerate() {
+            self.node(first + i as u32)
+                .reset(*mov, *ending, *eval, *prior);
+        }
+        // Release publishes the initialised children along with the range
+        self.node(id).children.store(
+            u64::from(first) << 32 | children.len() as u64,
+            AtomicOrdering::Release,
+        );
+        true
+    }
+
+    // Turns a claimed leaf into a link to an expanded node with the same
+    // position
+    pub fn link(&self, id: NodeId, target: NodeId) {
+        self.linked.store(true, AtomicOrdering::Relaxed);
+        let (node, shared) = (self.node(id), self.node(target));
+        node.set_eval(shared.eval());
+        node.set_ending(shared.ending());
+        node.set_depth(shared.depth());
+        node.children
+            .store(u64::from(target) << 32 | LINK, AtomicOrdering::Release);
+    }
+
+    pub fn transposition(&self, key: u64) -> Option<NodeId> {
+        self.transpositions.lock().unwrap().get(&key).copied()
+    }
+
+    // The first node expanded for a position is the one others link to
+    pub fn add_transposition(&self, key: u64, id: NodeId) {
+        self.transpositions.lock().unwrap().entry(key).or_insert(id);
+    }
+
+    // Bytes held by live nodes
+    pub fn bytes(&self) -> usize {
+        let used = self.next.load(AtomicOrdering::Relaxed) as usize;
+        (used - self.free_nodes.load(AtomicOrdering::Relaxed)) * mem::size_of::<Node>()
+    }
+
+    // Limits the arena to the given number of bytes.  Freed ranges do not
+    // always fit new nodes, so the arena can fill up before the live nodes
+    // reach the limit.
+    pub fn set_capacity(&self, bytes: usize) {
+        let nodes = bytes / mem::size_of::<Node>();
+        self.capacity.store(nodes, AtomicOrdering::Relaxed);
+    }
+
+    // True after an expansion failed for lack of room, until nodes are freed
+    pub fn is_full(&self) -> bool {
+        self.full.load(AtomicOrdering::Relaxed)
+    }
+
+    // Frees every descendant of the node, which becomes a leaf again.  Needs
+    // exclusive access, and a sweep once done collapsing.  Returns the bytes
+    // released, none when the node was itself freed by an earlier collapse.
+    pub fn collapse(&self, _gate: &RwLockWriteGuard<()>, id: NodeId) -> usize {
+        if self.node(id).children.load(AtomicOrdering::Relaxed) == FREED {
+            return 0;
+        }
+        self.free_descendants(id) * mem::size_of::<Node>()
+    }
+
+    // Cuts the links into freed nodes.  Freed nodes are not handed out again
+    // before the sweep, so they can still be told apart.
+    pub fn sweep(&self, _gate: &RwLockWriteGuard<()>) {
+        let is_freed = |id: NodeId| self.node(id).children.load(AtomicOrdering::Relaxed) == FREED;
+        self.transpositions
+            .lock()
+            .unwrap()
+            .retain(|_, id| !is_freed(*id));
+        if !self.linked.load(AtomicOrdering::Relaxed) {
+            return;
+        }
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            let target = self.resolve(id);
+            if target == id {
+                stack.extend(self.own_children(id));
+            } else if is_freed(target) {
+                self.unlink(id);
+            }
+        }
+    }
+
+    // A cut link becomes a leaf keeping the statistics last copied to it
+    fn unlink(&self, id: NodeId) {
+        let node = self.node(id);
+        node.set_pruned_visits(node.visits() - 1);
+        node.children.store(UNEXPANDED, AtomicOrdering::Relaxed);
+    }
+
+    // Makes the last of the game's positions the root when it is in the tree.
+    // The root's position is looked for in the game and the tree is followed
+    // down the positions played since, at any depth.  The rest of the tree is
+    // detached for free_detached to release.  Returns false, leaving the tree
+    // alone, when the new root is not found.
+    pub fn reroot(&mut self, positions: &[Board], history: Vec<u64>) -> bool {
+        let start = match positions
+            .iter()
+            .rposition(|board| same_position(board, &self.board))
+        {
+            Some(start) => start,
+            None => return false,
+        };
+        let mut new_root = self.root;
+        for pair in positions[start..].windows(2) {
+            let found = self
+                .children(new_root)
+                .find(|&child| same_position(&self.child_board(&pair[0], child), &pair[1]));
+            new_root = match found {
+                Some(child) => child,
+                None => return false,
+            };
+        }
+
+        if new_root != self.root {
+            // The root keeps its index, so the new root needs no room in an
+            // arena that may be full until the old tree is freed
+            let detached = self.own_children(self.root);
+            let kept = self.take(self.resolve(new_root));
+            self.node(self.root).copy_from(&kept);
+            self.detached.lock().unwrap().push(detached);
+            self.cut_detached_links();
+            self.board = *positions.last().unwrap();
+        }
+        self.history = history;
+        true
+    }
+
+    // Frees the detached nodes on another thread while the next search runs
+    pub fn free_detached_in_background(tree: &Arc<Tree>) {
+        let detached = Arc::clone(tree);
+        let handle = thread::spawn(move || detached.free_detached());
+        *tree.freeing.lock().unwrap() = Some(handle);
+    }
+
+    // Lets go of a tree that is no longer wanted.  Dropping a full arena
+    // takes a while, so the last owner does it on another thread.
+    pub fn drop_in_background(tree: Arc<Tree>) {
+        thread::spawn(move || drop(tree));
+    }
+
+    // Waits for the background free so its thread lets go of the tree
+    pub fn wait_for_free(&self) {
+        let handle = self.freeing.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.join().unwrap();
+        }
+    }
+
+    // Frees the nodes reroot detached.  Runs while the new root is searched,
+    // so each range is read before it is released for reuse.
+    pub fn free_detached(&self) {
+        let mut stack = mem::take(&mut *self.detached.lock().unwrap());
+        while let Some(range) = stack.pop() {
+            stack.extend(
+                range
+                    .clone()
+                    .map(|id| self.own_children(id))
+                    .filter(|children| !children.is_empty()),
+            );
+            if !range.is_empty() {
+                self.release(range.start, range.len());
+            }
+        }
+    }
+
+    // Links and transpositions into the detached nodes are dropped before
+    // they can be handed out again
+    fn cut_detached_links(&self) {
+        let mut transpositions = self.transpositions.lock().unwrap();
+        if transpositions.is_empty() && !self.linked.load(AtomicOrdering::Relaxed) {
+            return;
+        }
+        let mut kept = HashSet::new();
+        let mut links = Vec::new();
+        let mut stack: Vec<NodeId> = self.own_children(self.root).collect();
+        while let Some(id) = stack.pop() {
+            if self.resolve(id) == id {
+                kept.insert(id);
+                stack.extend(self.own_children(id));
+            } else {
+                links.push(id);
+            }
+        }
+        for id in links {
+            if !kept.contains(&self.resolve(id)) {
+                self.unlink(id);
+            }
+        }
+        transpositions.retain(|_, id| kept.contains(id));
+        // Links from elsewhere made visits through the root's moves that its
+        // own count does not have
+        let visits = self.position_visits(self.root);
+        self.node(self.root).set_visits(visits);
+    }
+
+    // Copies a node out of the tree, its children now belonging to the copy
+    fn take(&self, id: NodeId) -> Node {
+        let node = Node::default();
+        node.copy_from(self.node(id));
+        self.node(id)
+            .children
+            .store(UNEXPANDED, AtomicOrdering::Relaxed);
+        node
+    }
+
+    // Returns the number of nodes freed
+    fn free_descendants(&self, id: NodeId) -> usize {
+        let mut freed = 0;
+        let mut stack = vec![id];
+        while let Some(node) = stack.pop() {
+            let range = self.own_children(node);
+            let state = if node == id { UNEXPANDED } else { FREED };
+            self.node(node)
+                .children
+                .store(state, AtomicOrdering::Relaxed);
+            if range.is_empty() {
+                continue;
+            }
+            freed += range.len();
+            self.release(range.start, range.len());
+            stack.extend(range);
+        }
+        freed
+    }
+
+    fn alloc(&self, len: usize) -> Option<NodeId> {
+        if len > 0 && self.free_nodes.load(AtomicOrdering::Relaxed) > 0 {
+            if let Some(first) = self.reuse(len) {
+                return Some(first);
+            }
+        }
+
+        let capacity = self.capacity.load(AtomicOrdering::Relaxed);
+        let first =
+            self.next
+                .fetch_update(AtomicOrdering::Relaxed, AtomicOrdering::Relaxed, |next| {
+                    let end = next as usize + len;
+                    if end <= capacity && end <= u32::MAX as usize {
+                        Some(end as u32)
+                    } else {
+                        None
+                    }
+                });
+        let first = match first {
+            Ok(first) => first,
+            Err(_) => {
+                self.full.store(true, AtomicOrdering::Relaxed);
+                return None;
+            }
+        };
+        if len > 0 {
+            let last = first + len as u32 - 1;
+            for slab in (first >> SLAB_BITS)..=(last >> SLAB_BITS) {
+                self.slabs[slab as usize]
+                    .get_or_init(|| (0..SLAB_NODES).map(|_| Node::default()).collect());
+            }
+        }
+        Some(first)
+    }
+
+    // Takes the smallest free range that fits, returning what is left over
+    fn reuse(&self, len: usize) -> Option<NodeId> {
+        let mut free = self.free.lock().unwrap();
+        let fit = (len..=MAX_CHILDREN).find(|&n| !free[n].is_empty())?;
+        let first = free[fit].pop().unwrap();
+        if fit > len {
+            free[fit - len].push(first + len as u32);
+        }
+        self.free_nodes.fetch_sub(len, AtomicOrdering::Relaxed);
+        Some(first)
+    }
+
+    fn release(&self, first: NodeId, len: usize) {
+        self.free.lock().unwrap()[len].push(first);
+        self.free_nodes.fetch_add(len, AtomicOrdering::Relaxed);
+        self.full.store(false, AtomicOrdering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn expand_all(tree: &Tree, id: NodeId, board: &Board) {
+        let children: Vec<_> = move_gen::legal_moves(board)
+            .into_iter()
+            .map(|mov| (mov, None, 0.5, 1.0))
+            .collect();
+        assert!(tree.claim(id));
+        assert!(tree.expand(id, &children));
+    }
+
+    fn child(tree: &Tree, id: NodeId, mov: &str) -> NodeId {
+        tree.children(id)
+            .find(|&c| tree.node(c).mov().to_string() == mov)
+            .unwrap()
+    }
+
+    #[test]
+    fn nodes_are_compact() {
+        assert!(mem::size_of::<Node>() <= 40);
+    }
+
+    #[test]
+    fn capacity_limits_the_arena() {
+        let tree = Tree::new(Board::new(STARTPOS), Vec::new());
+        let root = tree.root();
+        tree.set_capacity(20 * mem::size_of::<Node>());
+        let children: Vec<_> = move_gen::legal_moves(&tree.board)
+            .into_iter()
+            .map(|mov| (mov, None, 0.5, 1.0))
+            .collect();
+        assert!(tree.claim(root));
+        assert!(!tree.expand(root, &children));
+        assert!(tree.is_full());
+        assert_eq!(tree.bytes(), mem::size_of::<Node>());
+
+        // The claim is given up so the node can be expanded once there is room
+        tree.set_capacity(21 * mem::size_of::<Node>());
+        expand_all(&tree, root, &tree.board);
+        assert_eq!(tree.children(root).len(), 20);
+    }
+
+    // The game's positions after each of the moves from the start position
+    fn game(moves: &[&str]) -> Vec<Board> {
+        let mut board = Board::new(STARTPOS);
+        let mut positions = vec![board];
+        for mov in moves.iter() {
+            board.do_move(mov);
+            positions.push(board);
+        }
+        positions
+    }
+
+    #[test]
+    fn reroot_keeps_the_subtree() {
+        let mut tree = Tree::new(Board::new(STARTPOS), Vec::new());
+        let mut id = tree.root();
+        let mut board = tree.board;
+        for mov in ["e2e4", "e7e5", "g1f3", "b8c6"].iter() {
+            expand_all(&tree, id, &board);
+            id = child(&tree, id, mov);
+            board = tree.child_board(&board, id);
+        }
+        expand_all(&tree, id, &board);
+        tree.node(id).set_visits(31);
+        let moves = tree.children(id).len();
+        let bytes = tree.bytes();
+
+        // A node four plies down becomes the root with its statistics and
+        // children, the old tree waiting to be freed
+        let positions = game(&["e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert!(tree.reroot(&positions, vec![1, 2, 3, 4]));
+        assert_eq!(tree.board, positions[4]);
+        assert_eq!(tree.history(), &[1, 2, 3, 4]);
+        assert_eq!(tree.node(tree.root()).visits(), 31);
+        assert_eq!(tree.children(tree.root()).len(), moves);
+        assert_eq!(tree.bytes(), bytes);
+        tree.free_detached();
+        assert_eq!(tree.bytes(), (1 + moves) * mem::size_of::<Node>());
+
+        // Freed ranges are handed out again before the arena grows, split
+        // when they are longer than needed
+        let used = tree.next.load(AtomicOrdering::Relaxed);
+        let free = tree.free_nodes.load(AtomicOrdering::Relaxed);
+        tree.alloc(10);
+        assert_eq!(tree.next.load(AtomicOrdering::Relaxed), used);
+        assert_eq!(tree.free_nodes.load(AtomicOrdering::Relaxed), free - 10);
+        for _ in 0..free - 10 {
+            tree.alloc(1);
+        }
+        assert_eq!(tree.next.load(AtomicOrdering::Relaxed), used);
+
+        let bb5 = child(&tree, tree.root(), "f1b5");
+        let bb5_board = tree.child_board(&tree.board, bb5);
+        expand_all(&tree, bb5, &bb5_board);
+        let replies = tree.children(bb5);
+
+        // A later position is found from the root's place in the game
+        let positions = game(&["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"]);
+        assert!(tree.reroot(&positions, Vec::new()));
+        assert_eq!(tree.children(tree.root()), replies);
+
+        // Positions outside the tree leave it alone
+        assert!(!tree.reroot(&game(&["d2d4"]), Vec::new()));
+        assert!(!tree.reroot(&game(&["e2e4", "e7e5", "g1f3"]), Vec::new()));
+        assert_eq!(tree.board, positions[5]);
+    }
+
+    #[test]
+    fn sweep_cuts_links_into_freed_nodes() {
+        let tree = Tree::new(Board::new(STARTPOS), Vec::new());
+        let root = tree.root();
+        expand_all(&tree, root, &tree.board);
+        let e4 = child(&tree, root, "e2e4");
+        let e4_board = tree.child_board(&tree.board, e4);
+        expand_all(&tree, e4, &e4_board);
+        let e5 = child(&tree, e4, "e7e5");
+        let e5_board = tree.child_board(&e4_board, e5);
+        expand_all(&tree, e5, &e5_board);
+        tree.add_transposition(e5_board.hash, e5);
+        tree.node(e5).set_eval(0.7);
+
+        // A link shares the children and values of the node it links to
+        let d4 = child(&tree, root, "d2d4");
+        assert!(tree.claim(d4));
+        tree.link(d4, e5);
+        assert_eq!(tree.children(d4), tree.children(e5));
+        assert_eq!(tree.value(d4).eval(), 0.7);
+        tree.node(d4).set_visits(12);
+
+        // Links into surviving nodes are left alone
+        let gate = tree.exclusive();
+        tree.collapse(&gate, e5);
+        tree.sweep(&gate);
+        assert_eq!(tree.resolve(d4), e5);
+
+        // and links into freed ones turn back into leaves
+        tree.collapse(&gate, e4);
+        tree.sweep(&gate);
+        assert_eq!(tree.resolve(d4), d4);
+        assert!(tree.children(d4).is_empty());
+        assert_eq!(tree.node(d4).eval(), 0.7);
+        assert_eq!(tree.position_visits(d4), 12);
+        assert_eq!(tree.transposition(e5_board.hash), None);
+        drop(gate);
+        assert!(tree.claim(d4));
+    }
+}
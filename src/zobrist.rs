@@ -0,0 +1,173 @@
+use crate::board::{Board, PieceType};
+use crate::magic;
+
+// Keys are generated at compile time from a fixed seed so hashes are stable
+// across runs and builds.
+const SEED: u64 = 0x9e3779b97f4a7c15;
+
+const PIECE_KEYS: [[u64; 64]; 12] = piece_keys();
+const CASTLE_KEYS: [u64; 4] = [key(768), key(769), key(770), key(771)];
+const EP_KEYS: [u64; 8] = [
+    key(772),
+    key(773),
+    key(774),
+    key(775),
+    key(776),
+    key(777),
+    key(778),
+    key(779),
+];
+pub const SIDE_KEY: u64 = key(780);
+
+// splitmix64 on the key index
+const fn key(index: u64) -> u64 {
+    let mut z = SEED.wrapping_add(index.wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const fn piece_keys() -> [[u64; 64]; 12] {
+    let mut keys = [[0; 64]; 12];
+    let mut pt = 0;
+    while pt < 12 {
+        let mut sq = 0;
+        while sq < 64 {
+            keys[pt][sq] = key((pt * 64 + sq) as u64);
+            sq += 1;
+        }
+        pt += 1;
+    }
+    keys
+}
+
+// Key for a single piece.  bb must have exactly one bit set.
+pub fn piece(pt: PieceType, bb: u64) -> u64 {
+    PIECE_KEYS[pt as usize][bb.trailing_zeros() as usize]
+}
+
+pub fn castling(board: &Board) -> u64 {
+    let mut hash = 0;
+    if board.is_w_castle {
+        hash ^= CASTLE_KEYS[0];
+    }
+    if board.is_w_q_castle {
+        hash ^= CASTLE_KEYS[1];
+    }
+    if board.is_b_castle {
+        hash ^= CASTLE_KEYS[2];
+    }
+    if board.is_b_q_castle {
+        hash ^= CASTLE_KEYS[3];
+    }
+    hash
+}
+
+// The en passent square only counts when the side to move has a pawn able to
+// capture onto it.  Otherwise the position is identical to one without it.
+pub fn en_passent(board: &Board) -> u64 {
+    match board.en_passent {
+        Some(ep_bb) => {
+            let pos = ep_bb.trailing_zeros() as usize;
+            let capturers = if board.is_w_move {
+                magic::b_pawn_attack_collisions[pos] & board.w_p_bb
+            } else {
+                magic::w_pawn_attack_collisions[pos] & board.b_p_bb
+            };
+            if capturers > 0 {
+                EP_KEYS[pos % 8]
+            } else {
+                0
+            }
+        }
+        None => 0,
+    }
+}
+
+// Key of everything besides piece placement
+pub fn state(board: &Board) -> u64 {
+    let side = if board.is_w_move { 0 } else { SIDE_KEY };
+    side ^ castling(board) ^ en_passent(board)
+}
+
+// Computes the full key from scratch
+pub fn hash(board: &Board) -> u64 {
+    let bbs = [
+        board.w_p_bb,
+        board.w_n_bb,
+        board.w_b_bb,
+        board.w_r_bb,
+        board.w_q_bb,
+        board.w_k_bb,
+        board.b_p_bb,
+        board.b_n_bb,
+        board.b_b_bb,
+        board.b_r_bb,
+        board.b_q_bb,
+        board.b_k_bb,
+    ];
+
+    let mut hash = state(board);
+    for (i, bb) in bbs.iter().enumerate() {
+        let mut bb = *bb;
+        while bb > 0 {
+            hash ^= PIECE_KEYS[i][bb.trailing_zeros() as usize];
+            // Strip the least significant bit
+            bb &= bb - 1;
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(fen: &str, moves: &str) -> Board {
+        let mut board = Board::new(fen);
+        for mov in moves.split_ascii_whitespace() {
+            board.do_move(mov);
+        }
+        board
+    }
+
+    const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn transpositions_share_a_key() {
+        let a = play(STARTPOS, "d2d4 g8f6 c2c4 e7e6");
+        let b = play(STARTPOS, "c2c4 e7e6 d2d4 g8f6");
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.hash, hash(&a));
+
+        // Knights out and back only changes the clocks
+        let c = play(STARTPOS, "g1f3 g8f6 f3g1 f6g8");
+        assert_eq!(c.hash, Board::new(STARTPOS).hash);
+    }
+
+    #[test]
+    fn state_changes_the_key() {
+        // Side to move
+        let w = Board::new("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        let b = Board::new("4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_ne!(w.hash, b.hash);
+
+        // Castling rights
+        let castle = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let no_castle = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        assert_ne!(castle.hash, no_castle.hash);
+        let moved = play(
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            "e1f1 e8f8 f1e1 f8e8",
+        );
+        assert_eq!(moved.hash, no_castle.hash);
+
+        // En passent only counts when it can be captured
+        let capturable = Board::new("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1");
+        let no_ep = Board::new("4k3/8/8/3Pp3/8/8/8/4K3 w - - 0 1");
+        assert_ne!(capturable.hash, no_ep.hash);
+        let uncapturable = Board::new("4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1");
+        let uncapturable_no_ep = Board::new("4k3/8/8/4p3/8/8/8/4K3 w - - 0 1");
+        assert_eq!(uncapturable.hash, uncapturable_no_ep.hash);
+    }
+}